@@ -1,7 +1,21 @@
-use std::env;
+use std::collections::HashMap;
+use std::sync::Arc;
 use dotenv::dotenv;
-use s3_gateway_rs::controller::s3::{delete_object, get_list_objects, get_valid_file_name, request_signed_url, PresignedObject};
-use salvo::{conn::tcp::TcpAcceptor, cors::Cors, http::header, hyper::Method, prelude::*};
+use s3_gateway_rs::auth::{authorize_file_name, authorize_file_name_access, get_access_scope, get_identity, AccessScope, AuthHoop};
+use s3_gateway_rs::config::{get_client, get_config, get_http_client, AntivirusMode, CollisionPolicy, DeleteMode, DownloadMode, GatewayConfig};
+use s3_gateway_rs::error::GatewayError;
+use s3_gateway_rs::controller::s3::{delete_container_objects, delete_object, get_list_objects, get_object_metadata, get_object_stat, get_object_stream, get_object_upload_policy, get_valid_file_name, put_object_stream, request_signed_get_url, request_signed_post_form, request_signed_put_url, resolve_file_name_collision, ListObjectsOptions, PresignedObject, Resource};
+use s3_gateway_rs::controller::multipart::{abort_multipart_upload, complete_multipart_upload, create_multipart_upload, get_list_multipart_uploads, request_signed_part_url, CompleteMultipartUploadRequest};
+use s3_gateway_rs::controller::metadata::{get_content_disposition, get_display_name, update_object_metadata, ObjectMetadata, COLUMN_NAME_KEY, CONTAINER_TYPE_KEY, ORIGINAL_FILE_NAME_KEY, RECORD_ID_KEY, TABLE_NAME_KEY, UPLOADED_BY_KEY};
+use s3_gateway_rs::controller::thumbnail::{get_thumbnail, ThumbnailContent};
+use s3_gateway_rs::controller::transfer::{transfer_objects, TransferDestination};
+use s3_gateway_rs::controller::trash::{get_list_trash, get_trash_path, move_to_trash, purge_trash, restore_from_trash, TRASH_PURGE_INTERVAL_SECONDS};
+use s3_gateway_rs::controller::versioning::{delete_object_version, get_list_object_versions, restore_object_version};
+use s3_gateway_rs::controller::antivirus::{insert_pending_scan_status, scan_object, scan_pending_objects, SCAN_PENDING_INTERVAL_SECONDS};
+use s3_gateway_rs::controller::archive::{get_archive_stream, get_container_archive};
+use s3_gateway_rs::controller::conditional::{format_http_date, ConditionalHeaders, Precondition};
+use futures_util::{Stream, StreamExt};
+use salvo::{affix, conn::tcp::TcpAcceptor, cors::Cors, http::header, hyper::Method, prelude::*};
 extern crate serde_json;
 use serde::Serialize;
 use simple_logger::SimpleLogger;
@@ -11,81 +25,176 @@ async fn main() {
     dotenv().ok();
     SimpleLogger::new().env().init().unwrap();
 
-	let port: String = match env::var("PORT") {
-        Ok(value) => value,
-        Err(_) => {
-			log::info!("Variable `PORT` Not found from enviroment, as default 7878");
-			"7878".to_owned()
-		}.to_owned()
+	let config: GatewayConfig = match GatewayConfig::from_env() {
+		Ok(value) => value,
+		Err(error) => {
+			log::error!("Invalid Gateway Configuration: `{:}`", error);
+			std::process::exit(1);
+		}
+	};
+	let client = match config.build_client() {
+		Ok(value) => value,
+		Err(error) => {
+			log::error!("Invalid S3 Client: `{:}`", error);
+			std::process::exit(1);
+		}
+	};
+
+	let http_client = match config.build_http_client() {
+		Ok(value) => value,
+		Err(error) => {
+			log::error!("Invalid HTTP Client: `{:}`", error);
+			std::process::exit(1);
+		}
+	};
+
+	let auth_hoop = match AuthHoop::from_config(&config) {
+		Ok(value) => value,
+		Err(error) => {
+			log::error!("Invalid Authentication: `{:}`", error);
+			std::process::exit(1);
+		}
 	};
+	if !auth_hoop.is_enabled() {
+		log::warn!("Authentication is disabled, set `AUTH_API_TOKENS`, `AUTH_JWT_SECRET` or `AUTH_JWKS_FILE` to enable it");
+	}
+
+	if config.delete_mode == DeleteMode::Trash {
+		let _config = config.clone();
+		let _client = client.clone();
+		tokio::spawn(async move {
+			loop {
+				match purge_trash(&_client, &_config).await {
+					Ok(_purged) => log::info!("Trash purged, {} expired files removed", _purged),
+					Err(error) => log::error!("Error purging trash: `{:}`", error),
+				}
+				tokio::time::sleep(std::time::Duration::from_secs(TRASH_PURGE_INTERVAL_SECONDS)).await;
+			}
+		});
+	}
+
+	if config.antivirus_mode != AntivirusMode::Disabled {
+		let _config = config.clone();
+		let _client = client.clone();
+		let _http_client = http_client.clone();
+		tokio::spawn(async move {
+			loop {
+				match scan_pending_objects(&_client, &_http_client, &_config).await {
+					Ok(_scanned) => log::info!("Pending uploads scanned, {} files checked", _scanned),
+					Err(error) => log::error!("Error scanning pending uploads: `{:}`", error),
+				}
+				tokio::time::sleep(std::time::Duration::from_secs(SCAN_PENDING_INTERVAL_SECONDS)).await;
+			}
+		});
+	}
 
-	let host: String = "0.0.0.0:".to_owned() + &port;
+	let host: String = "0.0.0.0:".to_owned() + &config.port;
 	log::info!("Server Address: {:?}", host.clone());
 	let acceptor: TcpAcceptor = TcpListener::new(&host).bind().await;
 
-    // TODO: Add support to allow requests from multiple origin
-    let allowed_origin = match env::var("ALLOWED_ORIGIN") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `ALLOWED_ORIGIN` Not found from enviroment");
-            "*".to_owned()
-        }.to_owned(),
-    };
-    let _s3_url =  match env::var("S3_URL") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `S3_URL` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _bucket_name =  match env::var("BUCKET_NAME") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `BUCKET_NAME` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _api_key =  match env::var("API_KEY") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `API_KEY` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _secret_key =  match env::var("SECRET_KEY") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `SECRET_KEY` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-
 	//  Send Device Info
     let cors_handler = Cors::new()
-        .allow_origin(&allowed_origin.to_owned())
-        .allow_methods(vec![Method::OPTIONS, Method::GET, Method::DELETE])
-        .allow_headers(vec![header::ACCESS_CONTROL_REQUEST_METHOD, header::ACCESS_CONTROL_REQUEST_HEADERS, header::AUTHORIZATION])
+        .allow_origin(&config.allowed_origin.to_owned())
+        .allow_methods(vec![Method::OPTIONS, Method::HEAD, Method::GET, Method::PUT, Method::POST, Method::DELETE])
+        .allow_headers(vec![header::ACCESS_CONTROL_REQUEST_METHOD, header::ACCESS_CONTROL_REQUEST_HEADERS, header::AUTHORIZATION, header::CONTENT_TYPE, header::RANGE, header::IF_RANGE, header::IF_MATCH, header::IF_NONE_MATCH, header::IF_MODIFIED_SINCE, header::IF_UNMODIFIED_SINCE])
+        .expose_headers(vec![header::CONTENT_LENGTH, header::CONTENT_RANGE, header::ACCEPT_RANGES, header::ETAG, header::LAST_MODIFIED, header::CONTENT_DISPOSITION])
         .into_handler()
     ;
 
 	let router = Router::new()
         .hoop(cors_handler)
+        .hoop(
+            affix::inject(Arc::new(config))
+                .inject(Arc::new(client))
+                .inject(http_client)
+        )
         .push(
 			// /api
 			Router::with_path("api")
+				.hoop(auth_hoop)
 				.options(options_response)
 				.get(get_system_info)
                 .push(
                     Router::with_path("resources")
 						.options(options_response)
                         .get(get_resources_file_container_based)
+                        .delete(delete_resources_container_based)
                         .push(
                             Router::with_path("<**file_name>")
 								.options(options_response)
+                                .head(head_resource)
                                 .get(get_resource)
+                                .put(upload_resource)
+                                .post(upload_resource)
                                 .delete(delete_resource)
                         )
                 )
+                .push(
+                    Router::with_path("archive")
+						.options(options_response)
+                        .get(get_resources_archive_container_based)
+                )
+                .push(
+                    Router::with_path("multipart-uploads")
+						.options(options_response)
+                        .get(get_multipart_uploads_container_based)
+                        .post(create_multipart_upload_container_based)
+                        .push(
+                            Router::with_path("<upload_id>")
+								.options(options_response)
+                                .post(complete_multipart_upload_file)
+                                .delete(abort_multipart_upload_file)
+                                .push(
+                                    Router::with_path("parts/<part_number>")
+										.options(options_response)
+                                        .get(get_presigned_url_put_part)
+                                )
+                        )
+                )
+                .push(
+                    Router::with_path("metadata/<**file_name>")
+						.options(options_response)
+                        .get(get_resource_metadata)
+                        .put(update_resource_metadata)
+                )
+                .push(
+                    Router::with_path("trash")
+						.options(options_response)
+                        .get(get_trash_resources)
+                        .push(
+                            Router::with_path("<**file_name>")
+								.options(options_response)
+                                .post(restore_trash_resource)
+                                .delete(delete_trash_resource)
+                        )
+                )
+                .push(
+                    Router::with_path("versions/<**file_name>")
+						.options(options_response)
+                        .get(get_resource_versions)
+                        .post(restore_resource_version)
+                        .delete(delete_resource_version)
+                )
+                .push(
+                    Router::with_path("copy/<**file_name>")
+						.options(options_response)
+                        .post(copy_resource)
+                )
+                .push(
+                    Router::with_path("move/<**file_name>")
+						.options(options_response)
+                        .post(move_resource)
+                )
+                .push(
+                    Router::with_path("thumbnails/<**file_name>")
+						.options(options_response)
+                        .get(get_resource_thumbnail)
+                )
+                .push(
+                    Router::with_path("scans/<**file_name>")
+						.options(options_response)
+                        .post(scan_resource)
+                )
                 .push(
                     Router::with_path("download-url/<**file_name>")
 						.options(options_response)
@@ -104,6 +213,19 @@ async fn main() {
 								.get(get_presigned_url_put_file_container_based)
 						)
 				)
+                .push(
+					Router::with_path("presigned-post/<client_id>")
+						.push(
+							Router::with_path("<file_name>")
+								.options(options_response)
+								.get(get_presigned_post_file_container_based)
+						)
+						.push(
+							Router::with_path("<container_id>/<file_name>")
+								.options(options_response)
+								.get(get_presigned_post_file_container_based)
+						)
+				)
         )
     ;
     log::info!("{:#?}", router);
@@ -122,17 +244,11 @@ struct SystemInfoResponse {
 }
 
 #[handler]
-async fn get_system_info<'a>(_req: &mut Request, _res: &mut Response) {
-	let version: String = match env::var("VERSION") {
-		Ok(value) => value,
-		Err(_) => {
-			log::info!("Variable `VERSION` Not found from enviroment, as default `1.0.0-dev`");
-			"1.0.0-dev".to_owned()
-		}.to_owned()
-	};
+async fn get_system_info<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+	let _config = get_config(_depot);
 
 	let system_info_response = SystemInfoResponse {
-		version: version.to_string(),
+		version: _config.version.to_string(),
 	};
 
 	_res.status_code(StatusCode::OK)
@@ -142,79 +258,593 @@ async fn get_system_info<'a>(_req: &mut Request, _res: &mut Response) {
 	;
 }
 
-#[derive(Serialize)]
-struct ErrorResponse {
-	status: u16,
-	message: String
+fn render_error(_res: &mut Response, error: GatewayError) {
+	match error {
+		GatewayError::Upstream(_) | GatewayError::Config(_) => log::error!("Interal Server Error: `{:}`", error),
+		_ => log::warn!("Request Error: `{:}`", error),
+	}
+	_res.render(error);
 }
 
+/// Headers of the S3 response sent back to the client on proxied downloads
+const FORWARDED_RESPONSE_HEADERS: [&str; 6] = ["content-type", "content-length", "content-range", "accept-ranges", "etag", "last-modified"];
+
 #[handler]
-async fn get_resource<'a>(_req: &mut Request, _res: &mut Response) {
+async fn get_resource<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
     let _file_name = _req.param::<String>("**file_name");
+    let _version_id = _req.query::<String>("version_id");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_file_name_access(_depot, _file_name) {
+            return render_error(_res, error)
+        }
+    }
     let _seconds = _req.query::<u32>("seconds");
-    if _file_name.is_some() {
-        match request_signed_url(_file_name.unwrap(), http::Method::GET, _seconds).await {
-            Ok(url) => _res.render(Redirect::permanent(url)),
-            Err(error) => {
-				log::error!("Interal Server Error: `{:}`", error.to_string());
-				let error_response = ErrorResponse {
-					status: StatusCode::INTERNAL_SERVER_ERROR.into(),
-					message: error.to_string()
-				};
-				_res.render(
-					Json(error_response)
-				);
-                _res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+    let _is_attachment = is_attachment_requested(_req);
+    match _file_name {
+        Some(_file_name) => {
+            let _conditional_headers = ConditionalHeaders::from_request(_req);
+            match _config.download_mode {
+                DownloadMode::Redirect => {
+                    //  The display name is only known from the object metadata
+                    let _stat = match get_object_stat(&_client, &_config, _file_name.to_owned(), _version_id.to_owned()).await {
+                        Ok(value) => value,
+                        Err(error) => return render_error(_res, error)
+                    };
+                    //  S3 evaluates the conditions after redirect, but the 304 must be answered here to avoid the round trip
+                    if _conditional_headers.has_preconditions() {
+                        match _conditional_headers.evaluate(Some(&_stat.etag), _stat.last_modified) {
+                            Precondition::NotModified => {
+                                _res.status_code(StatusCode::NOT_MODIFIED);
+                                let _ = _res.add_header(header::ETAG, format!("\"{}\"", _stat.etag), true);
+                                if let Some(_last_modified) = _stat.last_modified {
+                                    let _ = _res.add_header(header::LAST_MODIFIED, format_http_date(_last_modified), true);
+                                }
+                                return
+                            },
+                            Precondition::Failed => return render_error(_res, GatewayError::PreconditionFailed(format!("Precondition failed for `{}`", _file_name))),
+                            Precondition::Proceed => {}
+                        }
+                    }
+                    let _content_disposition = get_content_disposition(_is_attachment, &get_display_name(&_file_name, Some(&_stat.user_metadata)));
+                    match request_signed_get_url(&_client, &_config, _file_name, _version_id, Some(_content_disposition), _seconds).await {
+                        //  Presigned URL expires, it must not be cached as permanent
+                        Ok(url) => _res.render(Redirect::found(url)),
+                        Err(error) => render_error(_res, error)
+                    }
+                },
+                DownloadMode::Proxy => {
+                    let _http_client = get_http_client(_depot);
+                    match get_object_stream(&_client, &_http_client, &_config, _file_name.to_owned(), _version_id, _conditional_headers.to_forward_headers()).await {
+                        Ok(_response) => {
+                            let _user_metadata = _response.headers().get("x-amz-meta-original-file-name")
+                                .and_then(|value| value.to_str().ok())
+                                .map(|value| HashMap::from([(ORIGINAL_FILE_NAME_KEY.to_owned(), value.to_owned())]));
+                            let _content_disposition = get_content_disposition(_is_attachment, &get_display_name(&_file_name, _user_metadata.as_ref()));
+                            render_object_stream(_res, _response, _content_disposition)
+                        },
+                        Err(error) => render_error(_res, error)
+                    }
+                }
+            }
+        },
+        None => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string()))
+    }
+}
+
+#[handler]
+async fn head_resource<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _file_name = _req.param::<String>("**file_name");
+    let _version_id = _req.query::<String>("version_id");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_file_name_access(_depot, _file_name) {
+            return render_error(_res, error)
+        }
+    }
+    match _file_name {
+        Some(_file_name) => {
+            let _stat = match get_object_stat(&_client, &_config, _file_name.to_owned(), _version_id.to_owned()).await {
+                Ok(value) => value,
+                Err(error) => return render_error(_res, error)
+            };
+            let _conditional_headers = ConditionalHeaders::from_request(_req);
+            match _conditional_headers.evaluate(Some(&_stat.etag), _stat.last_modified) {
+                Precondition::NotModified => _res.status_code(StatusCode::NOT_MODIFIED),
+                Precondition::Failed => return render_error(_res, GatewayError::PreconditionFailed(format!("Precondition failed for `{}`", _file_name))),
+                Precondition::Proceed => _res.status_code(StatusCode::OK),
+            };
+            let _resource = Resource::from_stat(&_stat);
+            let _ = _res.add_header(header::ETAG, format!("\"{}\"", _stat.etag), true);
+            if let Some(_last_modified) = _stat.last_modified {
+                let _ = _res.add_header(header::LAST_MODIFIED, format_http_date(_last_modified), true);
             }
+            if let Some(_content_type) = _resource.content_type {
+                let _ = _res.add_header(header::CONTENT_TYPE, _content_type, true);
+            }
+            let _ = _res.add_header(header::CONTENT_LENGTH, _stat.size, true);
+            let _ = _res.add_header(header::ACCEPT_RANGES, "bytes", true);
+            if let Some(_display_name) = &_resource.display_name {
+                let _ = _res.add_header(header::CONTENT_DISPOSITION, get_content_disposition(is_attachment_requested(_req), _display_name), true);
+            }
+            for (_key, _value) in &_stat.user_metadata {
+                if let Ok(_name) = header::HeaderName::try_from(format!("x-amz-meta-{}", _key)) {
+                    let _ = _res.add_header(_name, _value, true);
+                }
+            }
+        },
+        None => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string()))
+    }
+}
+
+#[handler]
+async fn get_resource_metadata<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _file_name = _req.param::<String>("**file_name");
+    let _version_id = _req.query::<String>("version_id");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_file_name_access(_depot, _file_name) {
+            return render_error(_res, error)
+        }
+    }
+    match _file_name {
+        Some(_file_name) => match get_object_metadata(&_client, &_config, _file_name, _version_id).await {
+            Ok(_resource) => {
+                _res.status_code(StatusCode::OK)
+                    .render(
+                        Json(_resource)
+                    )
+                ;
+            },
+            Err(error) => render_error(_res, error)
+        },
+        None => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string()))
+    }
+}
+
+#[handler]
+async fn update_resource_metadata<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _file_name = _req.param::<String>("**file_name");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_file_name_access(_depot, _file_name) {
+            return render_error(_res, error)
+        }
+    }
+    let _metadata = match _req.parse_json::<ObjectMetadata>().await {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, GatewayError::Validation(format!("Invalid Metadata: {}", error)))
+    };
+    match _file_name {
+        Some(_file_name) => match update_object_metadata(&_client, &_config, _file_name, _metadata).await {
+            Ok(_resource) => {
+                _res.status_code(StatusCode::OK)
+                    .render(
+                        Json(_resource)
+                    )
+                ;
+            },
+            Err(error) => render_error(_res, error)
+        },
+        None => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string()))
+    }
+}
+
+/// `Content-Disposition` is `inline` unless the client asks to save the file with `disposition=attachment`
+fn is_attachment_requested(_req: &Request) -> bool {
+    _req.query::<String>("disposition").is_some_and(|value| value.eq("attachment"))
+}
+
+#[handler]
+async fn get_resource_versions<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _file_name = _req.param::<String>("**file_name");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_file_name_access(_depot, _file_name) {
+            return render_error(_res, error)
         }
-    } else {
-		log::error!("File Name is mandatory");
-		let error_response = ErrorResponse {
-			status: StatusCode::INTERNAL_SERVER_ERROR.into(),
-			message: "File Name is mandatory".to_string()
-		};
-		_res.render(
-			Json(error_response)
-		);
-        _res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    match _file_name {
+        Some(_file_name) => match get_list_object_versions(&_client, &_config, _file_name).await {
+            Ok(_versions) => {
+                _res.status_code(StatusCode::OK)
+                    .render(
+                        Json(_versions)
+                    )
+                ;
+            },
+            Err(error) => render_error(_res, error)
+        },
+        None => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string()))
     }
 }
 
 #[handler]
-async fn delete_resource<'a>(_req: &mut Request, _res: &mut Response) {
+async fn restore_resource_version<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
     let _file_name = _req.param::<String>("**file_name");
-    if _file_name.is_some() {
-        match delete_object(_file_name.unwrap()).await {
-            Ok(_) => {
-                
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_file_name_access(_depot, _file_name) {
+            return render_error(_res, error)
+        }
+    }
+    let _version_id = _req.query::<String>("version_id");
+    match (_file_name, _version_id) {
+        (Some(_file_name), Some(_version_id)) => match restore_object_version(&_client, &_config, _file_name, _version_id).await {
+            Ok(_resource) => {
+                _res.status_code(StatusCode::OK)
+                    .render(
+                        Json(_resource)
+                    )
+                ;
             },
-            Err(error) => {
-				log::error!("Interal Server Error: `{:}`", error.to_string());
-				let error_response: ErrorResponse = ErrorResponse {
-					status: StatusCode::INTERNAL_SERVER_ERROR.into(),
-					message: error.to_string()
-				};
-				_res.render(
-					Json(error_response)
-				);
-                _res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            Err(error) => render_error(_res, error)
+        },
+        (None, _) => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string())),
+        (_, None) => render_error(_res, GatewayError::Validation("Version ID is mandatory".to_string())),
+    }
+}
+
+#[handler]
+async fn delete_resource_version<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _file_name = _req.param::<String>("**file_name");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_file_name_access(_depot, _file_name) {
+            return render_error(_res, error)
+        }
+    }
+    let _version_id = _req.query::<String>("version_id");
+    match (_file_name, _version_id) {
+        (Some(_file_name), Some(_version_id)) => {
+            if let Err(error) = delete_object_version(&_client, &_config, _file_name, _version_id).await {
+                render_error(_res, error)
+            }
+        },
+        (None, _) => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string())),
+        (_, None) => render_error(_res, GatewayError::Validation("Version ID is mandatory".to_string())),
+    }
+}
+
+#[handler]
+async fn get_trash_resources<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _client_id = _req.query::<String>("client_id");
+    let AccessScope { client_id: _client_id, .. } = match get_access_scope(_depot, _client_id, None, None) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    let _options = ListObjectsOptions {
+        limit: _req.query::<u16>("limit"),
+        continuation_token: _req.query::<String>("continuation_token"),
+        fetch_all: _req.query::<bool>("fetch_all").unwrap_or(false),
+        ..ListObjectsOptions::default()
+    };
+    match get_list_trash(&_client, &_config, _client_id, _options).await {
+        Ok(mut _objects) => {
+            //  The trash is shared by the client, only the files of the session folders are returned
+            let _identity = get_identity(_depot);
+            if let Some(_resources) = &mut _objects.resources {
+                _resources.retain(|_resource| get_trash_path(&_resource.name)
+                    .and_then(|_trash_path| authorize_file_name(_identity.as_deref(), &_trash_path.file_name))
+                    .is_ok());
+            }
+            _res.render(Json(_objects))
+        },
+        Err(error) => render_error(_res, error)
+    }
+}
+
+/// Trash files are authorized with the key they had before the delete
+fn authorize_trash_file_name(_depot: &Depot, _file_name: &str) -> Result<(), GatewayError> {
+    let _trash_path = get_trash_path(_file_name)?;
+    authorize_file_name_access(_depot, &_trash_path.file_name)
+}
+
+#[handler]
+async fn restore_trash_resource<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _file_name = _req.param::<String>("**file_name");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_trash_file_name(_depot, _file_name) {
+            return render_error(_res, error)
+        }
+    }
+    let _collision_policy = match get_collision_policy(_req, &_config) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    match _file_name {
+        Some(_file_name) => match restore_from_trash(&_client, &_config, _file_name, _collision_policy).await {
+            Ok(_resource) => {
+                _res.status_code(StatusCode::OK)
+                    .render(
+                        Json(_resource)
+                    )
+                ;
+            },
+            Err(error) => render_error(_res, error)
+        },
+        None => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string()))
+    }
+}
+
+#[handler]
+async fn delete_trash_resource<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _file_name = _req.param::<String>("**file_name");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_trash_file_name(_depot, _file_name) {
+            return render_error(_res, error)
+        }
+    }
+    match _file_name {
+        Some(_file_name) => {
+            if let Err(error) = delete_object(&_client, &_config, _file_name).await {
+                render_error(_res, error)
+            }
+        },
+        None => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string()))
+    }
+}
+
+fn render_object_stream(_res: &mut Response, _response: reqwest::Response, _content_disposition: String) {
+    _res.status_code(StatusCode::from_u16(_response.status().as_u16()).unwrap_or(StatusCode::OK));
+    let _ = _res.add_header(header::CONTENT_DISPOSITION, _content_disposition, true);
+    for _name in FORWARDED_RESPONSE_HEADERS {
+        if let Some(_value) = _response.headers().get(_name).and_then(|value| value.to_str().ok()) {
+            let _ = _res.add_header(_name, _value, true);
+        }
+    }
+    _res.stream(_response.bytes_stream());
+}
+
+/// Collision policy sent as `on_conflict` or the one configured for the gateway
+fn get_collision_policy(_req: &Request, _config: &GatewayConfig) -> Result<CollisionPolicy, GatewayError> {
+    match _req.query::<String>("on_conflict") {
+        Some(value) => value.parse::<CollisionPolicy>(),
+        None => Ok(_config.collision_policy),
+    }
+}
+
+/// User of the session stored on uploads and deletes
+fn get_session_user(_depot: &Depot) -> Option<String> {
+    get_identity(_depot).and_then(|_identity| _identity.user_id.to_owned().or(_identity.subject.to_owned()))
+}
+
+/// Metadata sent by the caller plus the origin of the upload
+fn get_upload_metadata(_req: &Request, _depot: &Depot, _file_name: &Option<String>, _container_type: &Option<String>, _table_name: &Option<String>, _column_name: &Option<String>, _record_id: &Option<String>) -> ObjectMetadata {
+    let mut _metadata = ObjectMetadata::from_request(_req);
+    _metadata.insert_user_metadata(ORIGINAL_FILE_NAME_KEY, _file_name.to_owned());
+    _metadata.insert_user_metadata(UPLOADED_BY_KEY, get_session_user(_depot));
+    _metadata.insert_user_metadata(CONTAINER_TYPE_KEY, _container_type.to_owned());
+    _metadata.insert_user_metadata(TABLE_NAME_KEY, _table_name.to_owned());
+    _metadata.insert_user_metadata(COLUMN_NAME_KEY, _column_name.to_owned());
+    _metadata.insert_user_metadata(RECORD_ID_KEY, _record_id.to_owned());
+    insert_pending_scan_status(&get_config(_depot), &mut _metadata);
+    _metadata
+}
+
+/// Request body as a stream of data frames, trailers are discarded
+fn get_body_stream(_req: &mut Request) -> impl Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send + Sync + 'static {
+    _req.take_body().filter_map(|_frame| async move {
+        match _frame {
+            Ok(frame) => frame.into_data().ok().map(Ok),
+            Err(error) => Some(Err(error)),
+        }
+    })
+}
+
+#[handler]
+async fn upload_resource<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _http_client = get_http_client(_depot);
+    let _client_id = _req.query::<String>("client_id");
+    let _container_id = _req.query::<String>("container_id");
+    let _file_name = _req.param::<String>("**file_name");
+    let _container_type = _req.query::<String>("container_type");
+    let _table_name = _req.query::<String>("table_name");
+    let _column_name = _req.query::<String>("column_name");
+    let _record_id = _req.query::<String>("record_id");
+    let _user_id = _req.query::<String>("user_id");
+    let _role_id = _req.query::<String>("role_id");
+    let AccessScope { client_id: _client_id, user_id: _user_id, role_id: _role_id } = match get_access_scope(_depot, _client_id, _user_id, _role_id) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    let _content_type = _req.header::<String>(header::CONTENT_TYPE);
+    let _content_length = _req.header::<usize>(header::CONTENT_LENGTH);
+    let _metadata = get_upload_metadata(_req, _depot, &_file_name, &_container_type, &_table_name, &_column_name, &_record_id);
+    let _collision_policy = match get_collision_policy(_req, &_config) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    //  Get Valid File Name
+    let _file_name_to_store = get_valid_file_name(_client_id, _container_id, _file_name, _container_type, _table_name, _column_name, _record_id, _user_id, _role_id);
+    match _file_name_to_store {
+        Ok(_valid_file_name) => {
+            let _valid_file_name = match resolve_file_name_collision(&_client, &_config, _valid_file_name, _collision_policy).await {
+                Ok(value) => value,
+                Err(error) => return render_error(_res, error)
+            };
+            match _content_length {
+                Some(_content_length) => {
+                    let _stream = get_body_stream(_req);
+                    match put_object_stream(&_client, &_http_client, &_config, _valid_file_name, _content_type, _content_length, &_metadata, _stream).await {
+                        Ok(_object) => {
+                            _res.status_code(StatusCode::CREATED)
+                                .render(Json(_object));
+                        },
+                        Err(error) => render_error(_res, error)
+                    }
+                },
+                None => render_error(_res, GatewayError::Validation("Content-Length is Mandatory".to_string()))
             }
+        },
+        Err(error) => render_error(_res, error)
+    }
+}
+
+#[handler]
+async fn delete_resource<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _file_name = _req.param::<String>("**file_name");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_file_name_access(_depot, _file_name) {
+            return render_error(_res, error)
         }
-    } else {
-		log::error!("File Name is mandatory");
-		let error_response: ErrorResponse = ErrorResponse {
-			status: StatusCode::INTERNAL_SERVER_ERROR.into(),
-			message: "File Name is mandatory".to_string()
-		};
-		_res.render(
-			Json(error_response)
-		);
-        _res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    //  Large files or cleanups can skip the trash
+    let _is_permanent = _config.delete_mode == DeleteMode::Permanent || _req.query::<bool>("permanent").unwrap_or(false);
+    match _file_name {
+        Some(_file_name) if !_is_permanent => match move_to_trash(&_client, &_config, _file_name, get_session_user(_depot)).await {
+            Ok(_resource) => {
+                _res.status_code(StatusCode::OK)
+                    .render(
+                        Json(_resource)
+                    )
+                ;
+            },
+            Err(error) => render_error(_res, error)
+        },
+        Some(_file_name) => {
+            if let Err(error) = delete_object(&_client, &_config, _file_name).await {
+                render_error(_res, error)
+            }
+        },
+        None => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string()))
+    }
+}
+
+#[handler]
+async fn get_resource_thumbnail<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _http_client = get_http_client(_depot);
+    let _file_name = match _req.param::<String>("**file_name") {
+        Some(value) => value,
+        None => return render_error(_res, GatewayError::Validation("File Name is mandatory".to_string()))
+    };
+    if let Err(error) = authorize_file_name_access(_depot, &_file_name) {
+        return render_error(_res, error)
+    }
+    let _width = _req.query::<u32>("width");
+    let _height = _req.query::<u32>("height");
+    let _format = _req.query::<String>("format");
+    match get_thumbnail(&_client, &_http_client, &_config, _file_name, _width, _height, _format).await {
+        Ok(_thumbnail) => {
+            let _content_disposition = get_content_disposition(false, &_thumbnail.display_name);
+            match _thumbnail.content {
+                ThumbnailContent::Cached(_response) => render_object_stream(_res, _response, _content_disposition),
+                ThumbnailContent::Generated(_data) => {
+                    _res.status_code(StatusCode::OK);
+                    let _ = _res.add_header(header::CONTENT_DISPOSITION, _content_disposition, true);
+                    let _ = _res.add_header(header::CONTENT_TYPE, _thumbnail.content_type, true);
+                    if let Err(error) = _res.write_body(_data) {
+                        log::error!("Error writing thumbnail: {}", error);
+                    }
+                }
+            }
+        },
+        Err(error) => render_error(_res, error)
+    }
+}
+
+#[handler]
+async fn scan_resource<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _http_client = get_http_client(_depot);
+    let _file_name = match _req.param::<String>("**file_name") {
+        Some(value) => value,
+        None => return render_error(_res, GatewayError::Validation("File Name is mandatory".to_string()))
+    };
+    if let Err(error) = authorize_file_name_access(_depot, &_file_name) {
+        return render_error(_res, error)
+    }
+    match scan_object(&_client, &_http_client, &_config, _file_name).await {
+        Ok(_response) => {
+            _res.status_code(StatusCode::OK)
+                .render(
+                    Json(_response)
+                )
+            ;
+        },
+        Err(error) => render_error(_res, error)
     }
 }
 
 #[handler]
-async fn get_resources_file_container_based<'a>(_req: &mut Request, _res: &mut Response) {
+async fn copy_resource<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    transfer_resource(_req, _depot, _res, false).await
+}
+
+#[handler]
+async fn move_resource<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    transfer_resource(_req, _depot, _res, true).await
+}
+
+/// Server side copy or move of a resource (or a folder with `recursive=true`) to the container of the query parameters
+async fn transfer_resource(_req: &mut Request, _depot: &mut Depot, _res: &mut Response, _is_move: bool) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _file_name = match _req.param::<String>("**file_name") {
+        Some(value) => value,
+        None => return render_error(_res, GatewayError::Validation("File Name is mandatory".to_string()))
+    };
+    if let Err(error) = authorize_file_name_access(_depot, &_file_name) {
+        return render_error(_res, error)
+    }
+    let _client_id = _req.query::<String>("client_id");
+    let _user_id = _req.query::<String>("user_id");
+    let _role_id = _req.query::<String>("role_id");
+    let AccessScope { client_id: _client_id, user_id: _user_id, role_id: _role_id } = match get_access_scope(_depot, _client_id, _user_id, _role_id) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    let _destination = TransferDestination {
+        client_id: _client_id,
+        container_id: _req.query::<String>("container_id"),
+        container_type: _req.query::<String>("container_type"),
+        table_name: _req.query::<String>("table_name"),
+        column_name: _req.query::<String>("column_name"),
+        record_id: _req.query::<String>("record_id"),
+        user_id: _user_id,
+        role_id: _role_id,
+    };
+    let _collision_policy = match get_collision_policy(_req, &_config) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    let _is_recursive = _req.query::<bool>("recursive").unwrap_or(false);
+    match transfer_objects(&_client, &_config, _file_name, _destination, _is_recursive, _is_move, _collision_policy).await {
+        Ok(_response) => {
+            _res.status_code(StatusCode::OK)
+                .render(
+                    Json(_response)
+                )
+            ;
+        },
+        Err(error) => render_error(_res, error)
+    }
+}
+
+#[handler]
+async fn get_resources_file_container_based<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
     let _client_id = _req.query::<String>("client_id");
     let _container_id = _req.query::<String>("container_id");
     let _file_name = _req.param::<String>("file_name");
@@ -224,28 +854,93 @@ async fn get_resources_file_container_based<'a>(_req: &mut Request, _res: &mut R
     let _record_id = _req.query::<String>("record_id");
     let _user_id = _req.query::<String>("user_id");
     let _role_id = _req.query::<String>("role_id");
+    let AccessScope { client_id: _client_id, user_id: _user_id, role_id: _role_id } = match get_access_scope(_depot, _client_id, _user_id, _role_id) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
     let _seconds = _req.query::<u32>("seconds");
-    let _object_list = get_list_objects(_client_id.to_owned(), _container_id.to_owned(), _container_type.to_owned(), _table_name.to_owned(), _column_name.to_owned(), _record_id.to_owned(), _user_id.to_owned(), _role_id.to_owned()).await;
+    let _options = ListObjectsOptions {
+        limit: _req.query::<u16>("limit"),
+        continuation_token: _req.query::<String>("continuation_token"),
+        fetch_all: _req.query::<bool>("fetch_all").unwrap_or(false),
+        recursive: _req.query::<bool>("recursive").unwrap_or(true),
+    };
+    let _object_list = get_list_objects(&_client, &_config, _client_id.to_owned(), _container_id.to_owned(), _container_type.to_owned(), _table_name.to_owned(), _column_name.to_owned(), _record_id.to_owned(), _user_id.to_owned(), _role_id.to_owned(), _options).await;
     match _object_list {
         Ok(_objects) => {
            _res.render(Json(_objects))
         },
-        Err(error) => {
-			log::error!("Interal Server Error: `{:}`", error.to_string());
-			let error_response: ErrorResponse = ErrorResponse {
-				status: StatusCode::INTERNAL_SERVER_ERROR.into(),
-				message: error.to_string()
-			};
-			_res.render(
-				Json(error_response)
-			);
-            _res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+        Err(error) => render_error(_res, error)
     }
 }
 
 #[handler]
-async fn get_presigned_url_put_file_container_based<'a>(_req: &mut Request, _res: &mut Response) {
+async fn delete_resources_container_based<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _client_id = _req.query::<String>("client_id");
+    let _container_id = _req.query::<String>("container_id");
+    let _container_type = _req.query::<String>("container_type");
+    let _table_name = _req.query::<String>("table_name");
+    let _column_name = _req.query::<String>("column_name");
+    let _record_id = _req.query::<String>("record_id");
+    let _user_id = _req.query::<String>("user_id");
+    let _role_id = _req.query::<String>("role_id");
+    let AccessScope { client_id: _client_id, user_id: _user_id, role_id: _role_id } = match get_access_scope(_depot, _client_id, _user_id, _role_id) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    //  The whole container is removed, the caller must confirm it
+    if !_req.query::<bool>("confirm").unwrap_or(false) {
+        log::error!("Bulk Delete without confirmation");
+        return render_error(_res, GatewayError::Validation("Confirmation is mandatory (confirm=true)".to_string()))
+    }
+    let _is_permanent = _config.delete_mode == DeleteMode::Permanent || _req.query::<bool>("permanent").unwrap_or(false);
+    match delete_container_objects(&_client, &_config, _client_id, _container_id, _container_type, _table_name, _column_name, _record_id, _user_id, _role_id, _is_permanent, get_session_user(_depot)).await {
+        Ok(_response) => {
+            _res.status_code(StatusCode::OK)
+                .render(
+                    Json(_response)
+                )
+            ;
+        },
+        Err(error) => render_error(_res, error)
+    }
+}
+
+#[handler]
+async fn get_resources_archive_container_based<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _http_client = get_http_client(_depot);
+    let _client_id = _req.query::<String>("client_id");
+    let _container_id = _req.query::<String>("container_id");
+    let _container_type = _req.query::<String>("container_type");
+    let _table_name = _req.query::<String>("table_name");
+    let _column_name = _req.query::<String>("column_name");
+    let _record_id = _req.query::<String>("record_id");
+    let _user_id = _req.query::<String>("user_id");
+    let _role_id = _req.query::<String>("role_id");
+    let AccessScope { client_id: _client_id, user_id: _user_id, role_id: _role_id } = match get_access_scope(_depot, _client_id, _user_id, _role_id) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    match get_container_archive(&_client, &_config, _client_id, _container_id, _container_type, _table_name, _column_name, _record_id, _user_id, _role_id).await {
+        Ok(_archive) => {
+            _res.status_code(StatusCode::OK);
+            let _ = _res.add_header(header::CONTENT_TYPE, "application/zip", true);
+            let _ = _res.add_header(header::CONTENT_DISPOSITION, get_content_disposition(true, &_archive.file_name), true);
+            //  The archive is built while it is sent, an error after the headers truncates the download
+            _res.stream(get_archive_stream(_client, _http_client, _config, _archive.entries));
+        },
+        Err(error) => render_error(_res, error)
+    }
+}
+
+#[handler]
+async fn get_presigned_url_put_file_container_based<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
     let _client_id = _req.param::<String>("client_id");
     let _container_id = _req.param::<String>("container_id");
     let _file_name = _req.param::<String>("file_name");
@@ -255,72 +950,256 @@ async fn get_presigned_url_put_file_container_based<'a>(_req: &mut Request, _res
     let _record_id = _req.query::<String>("record_id");
     let _user_id = _req.query::<String>("user_id");
     let _role_id = _req.query::<String>("role_id");
+    let AccessScope { client_id: _client_id, user_id: _user_id, role_id: _role_id } = match get_access_scope(_depot, _client_id, _user_id, _role_id) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
     let _seconds = _req.query::<u32>("seconds");
-    let _ = get_list_objects(_client_id.to_owned(), _container_id.to_owned(), _container_type.to_owned(), _table_name.to_owned(), _column_name.to_owned(), _record_id.to_owned(), _user_id.to_owned(), _role_id.to_owned()).await;
+    let _metadata = get_upload_metadata(_req, _depot, &_file_name, &_container_type, &_table_name, &_column_name, &_record_id);
+    let _collision_policy = match get_collision_policy(_req, &_config) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
     //  Get Valid File Name
     let _file_name_to_store = get_valid_file_name(_client_id, _container_id, _file_name, _container_type, _table_name, _column_name, _record_id, _user_id, _role_id);
     match _file_name_to_store {
         Ok(_valid_file_name) => {
-            match request_signed_url(_valid_file_name.to_owned(), http::Method::PUT, _seconds).await {
+            let _valid_file_name = match resolve_file_name_collision(&_client, &_config, _valid_file_name, _collision_policy).await {
+                Ok(value) => value,
+                Err(error) => return render_error(_res, error)
+            };
+            //  A PUT URL accepts any size and type, the uploads with policy are sent as POST forms
+            if let Some(_upload_policy) = get_object_upload_policy(&_config, &_valid_file_name) {
+                return match request_signed_post_form(&_client, &_config, _valid_file_name, &_metadata, _req.query::<String>("content_type"), None, Some(_upload_policy), _seconds).await {
+                    Ok(_form) => _res.render(Json(_form)),
+                    Err(error) => render_error(_res, error)
+                }
+            }
+            match request_signed_put_url(&_client, &_config, _valid_file_name.to_owned(), &_metadata, _seconds).await {
                 Ok(url) => _res.render(Json(PresignedObject {
                     url: Some(url),
-                    file_name: Some(_valid_file_name)
+                    file_name: Some(_valid_file_name),
+                    method: Some(Method::PUT.to_string()),
+                    form_data: None,
                 })),
-                Err(error) => {
-					log::error!("Interal Server Error: `{:}`", error.to_string());
-					let error_response: ErrorResponse = ErrorResponse {
-						status: StatusCode::INTERNAL_SERVER_ERROR.into(),
-						message: error.to_string()
-					};
-					_res.render(
-						Json(error_response)
-					);
-                    _res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-                }
+                Err(error) => render_error(_res, error)
             }
         },
-        Err(error) => {
-			log::error!("Interal Server Error: `{:}`", error.to_string());
-			let error_response: ErrorResponse = ErrorResponse {
-				status: StatusCode::INTERNAL_SERVER_ERROR.into(),
-				message: error.to_string()
-			};
-			_res.render(
-				Json(error_response)
-			);
-            _res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+        Err(error) => render_error(_res, error)
+    }
+}
+
+#[handler]
+async fn get_presigned_post_file_container_based<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _client_id = _req.param::<String>("client_id");
+    let _container_id = _req.param::<String>("container_id");
+    let _file_name = _req.param::<String>("file_name");
+    let _container_type = _req.query::<String>("container_type");
+    let _table_name = _req.query::<String>("table_name");
+    let _column_name = _req.query::<String>("column_name");
+    let _record_id = _req.query::<String>("record_id");
+    let _user_id = _req.query::<String>("user_id");
+    let _role_id = _req.query::<String>("role_id");
+    let AccessScope { client_id: _client_id, user_id: _user_id, role_id: _role_id } = match get_access_scope(_depot, _client_id, _user_id, _role_id) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    let _seconds = _req.query::<u32>("seconds");
+    let _content_type = _req.query::<String>("content_type");
+    let _max_size = _req.query::<usize>("max_size");
+    let _metadata = get_upload_metadata(_req, _depot, &_file_name, &_container_type, &_table_name, &_column_name, &_record_id);
+    let _collision_policy = match get_collision_policy(_req, &_config) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    //  Get Valid File Name
+    let _file_name_to_store = get_valid_file_name(_client_id, _container_id, _file_name, _container_type, _table_name, _column_name, _record_id, _user_id, _role_id);
+    match _file_name_to_store {
+        Ok(_valid_file_name) => {
+            let _valid_file_name = match resolve_file_name_collision(&_client, &_config, _valid_file_name, _collision_policy).await {
+                Ok(value) => value,
+                Err(error) => return render_error(_res, error)
+            };
+            let _upload_policy = get_object_upload_policy(&_config, &_valid_file_name);
+            match request_signed_post_form(&_client, &_config, _valid_file_name, &_metadata, _content_type, _max_size, _upload_policy, _seconds).await {
+                Ok(_form) => _res.render(Json(_form)),
+                Err(error) => render_error(_res, error)
+            }
+        },
+        Err(error) => render_error(_res, error)
     }
 }
 
 #[handler]
-async fn get_presigned_url_download_file<'a>(_req: &mut Request, _res: &mut Response) {
+async fn get_presigned_url_download_file<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
     let _file_name = _req.param::<String>("**file_name");
+    let _version_id = _req.query::<String>("version_id");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_file_name_access(_depot, _file_name) {
+            return render_error(_res, error)
+        }
+    }
     let _seconds = _req.query::<u32>("seconds");
-    if _file_name.is_some() {
-        match request_signed_url(_file_name.unwrap(), http::Method::GET, _seconds).await {
-            Ok(url) => _res.render(Json(url)),
-            Err(error) => {
-				log::error!("Interal Server Error: `{:}`", error.to_string());
-				let error_response: ErrorResponse = ErrorResponse {
-					status: StatusCode::INTERNAL_SERVER_ERROR.into(),
-					message: error.to_string()
-				};
-				_res.render(
-					Json(error_response)
-				);
-                _res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+    let _is_attachment = is_attachment_requested(_req);
+    match _file_name {
+        Some(_file_name) => {
+            let _stat = match get_object_stat(&_client, &_config, _file_name.to_owned(), _version_id.to_owned()).await {
+                Ok(value) => value,
+                Err(error) => return render_error(_res, error)
+            };
+            let _content_disposition = get_content_disposition(_is_attachment, &get_display_name(&_file_name, Some(&_stat.user_metadata)));
+            match request_signed_get_url(&_client, &_config, _file_name, _version_id, Some(_content_disposition), _seconds).await {
+                Ok(url) => _res.render(Json(url)),
+                Err(error) => render_error(_res, error)
             }
+        },
+        None => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string()))
+    }
+}
+
+#[handler]
+async fn get_multipart_uploads_container_based<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _client_id = _req.query::<String>("client_id");
+    let _container_id = _req.query::<String>("container_id");
+    let _container_type = _req.query::<String>("container_type");
+    let _table_name = _req.query::<String>("table_name");
+    let _column_name = _req.query::<String>("column_name");
+    let _record_id = _req.query::<String>("record_id");
+    let _user_id = _req.query::<String>("user_id");
+    let _role_id = _req.query::<String>("role_id");
+    let AccessScope { client_id: _client_id, user_id: _user_id, role_id: _role_id } = match get_access_scope(_depot, _client_id, _user_id, _role_id) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    match get_list_multipart_uploads(&_client, &_config, _client_id, _container_id, _container_type, _table_name, _column_name, _record_id, _user_id, _role_id).await {
+        Ok(_uploads) => _res.render(Json(_uploads)),
+        Err(error) => render_error(_res, error)
+    }
+}
+
+#[handler]
+async fn create_multipart_upload_container_based<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _client_id = _req.query::<String>("client_id");
+    let _container_id = _req.query::<String>("container_id");
+    let _file_name = _req.query::<String>("file_name");
+    let _container_type = _req.query::<String>("container_type");
+    let _table_name = _req.query::<String>("table_name");
+    let _column_name = _req.query::<String>("column_name");
+    let _record_id = _req.query::<String>("record_id");
+    let _user_id = _req.query::<String>("user_id");
+    let _role_id = _req.query::<String>("role_id");
+    let _size = _req.query::<usize>("size");
+    let AccessScope { client_id: _client_id, user_id: _user_id, role_id: _role_id } = match get_access_scope(_depot, _client_id, _user_id, _role_id) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    let _metadata = get_upload_metadata(_req, _depot, &_file_name, &_container_type, &_table_name, &_column_name, &_record_id);
+    let _collision_policy = match get_collision_policy(_req, &_config) {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, error)
+    };
+    //  Get Valid File Name
+    let _file_name_to_store = get_valid_file_name(_client_id, _container_id, _file_name, _container_type, _table_name, _column_name, _record_id, _user_id, _role_id);
+    match _file_name_to_store {
+        Ok(_valid_file_name) => {
+            let _valid_file_name = match resolve_file_name_collision(&_client, &_config, _valid_file_name, _collision_policy).await {
+                Ok(value) => value,
+                Err(error) => return render_error(_res, error)
+            };
+            match create_multipart_upload(&_client, &_config, _valid_file_name, _size, &_metadata).await {
+                Ok(_upload) => {
+                    _res.status_code(StatusCode::CREATED)
+                        .render(Json(_upload));
+                },
+                Err(error) => render_error(_res, error)
+            }
+        },
+        Err(error) => render_error(_res, error)
+    }
+}
+
+#[handler]
+async fn get_presigned_url_put_part<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _upload_id = _req.param::<String>("upload_id");
+    let _part_number = _req.param::<u16>("part_number");
+    let _file_name = _req.query::<String>("file_name");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_file_name_access(_depot, _file_name) {
+            return render_error(_res, error)
+        }
+    }
+    let _seconds = _req.query::<u32>("seconds");
+    match (_file_name, _upload_id, _part_number) {
+        (Some(_file_name), Some(_upload_id), Some(_part_number)) => {
+            match request_signed_part_url(&_client, &_config, _file_name, _upload_id, _part_number, _seconds).await {
+                Ok(_part) => _res.render(Json(_part)),
+                Err(error) => render_error(_res, error)
+            }
+        },
+        (None, _, _) => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string())),
+        (_, None, _) => render_error(_res, GatewayError::Validation("Upload ID is mandatory".to_string())),
+        (_, _, None) => render_error(_res, GatewayError::Validation("Invalid Part Number".to_string())),
+    }
+}
+
+#[handler]
+async fn complete_multipart_upload_file<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _upload_id = _req.param::<String>("upload_id");
+    let _file_name = _req.query::<String>("file_name");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_file_name_access(_depot, _file_name) {
+            return render_error(_res, error)
         }
-    } else {
-		log::error!("File Name is mandatory");
-		let error_response: ErrorResponse = ErrorResponse {
-			status: StatusCode::INTERNAL_SERVER_ERROR.into(),
-			message: "File Name is mandatory".to_string()
-		};
-		_res.render(
-			Json(error_response)
-		);
-        _res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    let _request = match _req.parse_json::<CompleteMultipartUploadRequest>().await {
+        Ok(value) => value,
+        Err(error) => return render_error(_res, GatewayError::Validation(format!("Invalid Parts: {}", error)))
+    };
+    match (_file_name, _upload_id) {
+        (Some(_file_name), Some(_upload_id)) => {
+            match complete_multipart_upload(&_client, &_config, _file_name, _upload_id, _request.parts).await {
+                Ok(_object) => {
+                    _res.status_code(StatusCode::CREATED)
+                        .render(Json(_object));
+                },
+                Err(error) => render_error(_res, error)
+            }
+        },
+        (None, _) => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string())),
+        (_, None) => render_error(_res, GatewayError::Validation("Upload ID is mandatory".to_string())),
+    }
+}
+
+#[handler]
+async fn abort_multipart_upload_file<'a>(_req: &mut Request, _depot: &mut Depot, _res: &mut Response) {
+    let _config = get_config(_depot);
+    let _client = get_client(_depot);
+    let _upload_id = _req.param::<String>("upload_id");
+    let _file_name = _req.query::<String>("file_name");
+    if let Some(_file_name) = &_file_name {
+        if let Err(error) = authorize_file_name_access(_depot, _file_name) {
+            return render_error(_res, error)
+        }
+    }
+    match (_file_name, _upload_id) {
+        (Some(_file_name), Some(_upload_id)) => {
+            if let Err(error) = abort_multipart_upload(&_client, &_config, _file_name, _upload_id).await {
+                render_error(_res, error)
+            }
+        },
+        (None, _) => render_error(_res, GatewayError::Validation("File Name is mandatory".to_string())),
+        (_, None) => render_error(_res, GatewayError::Validation("Upload ID is mandatory".to_string())),
     }
 }
@@ -0,0 +1,442 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::env;
+
+use minio::s3::client::Client;
+use minio::s3::creds::StaticProvider;
+use minio::s3::http::BaseUrl;
+use salvo::Depot;
+use serde::Deserialize;
+
+use crate::error::GatewayError;
+
+/// How the objects are delivered on `GET /api/resources/<file_name>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadMode {
+    /// Redirect the client to a presigned S3 URL
+    Redirect,
+    /// Stream the object body through the gateway
+    Proxy,
+}
+
+/// How the objects are removed on `DELETE /api/resources/<file_name>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeleteMode {
+    /// Remove the object from S3
+    Permanent,
+    /// Move the object to the `trash/` folder of the client until the retention expires
+    Trash,
+}
+
+/// What to do when an upload targets an existing object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Replace the existing object
+    Overwrite,
+    /// Answer with `409 Conflict`
+    Reject,
+    /// Store the upload with a suffix on the name
+    Rename,
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = GatewayError;
+
+    fn from_str(_value: &str) -> Result<Self, Self::Err> {
+        match _value.to_lowercase().as_str() {
+            "overwrite" => Ok(CollisionPolicy::Overwrite),
+            "reject" => Ok(CollisionPolicy::Reject),
+            "rename" => Ok(CollisionPolicy::Rename),
+            _ => Err(GatewayError::Validation(format!("Invalid collision policy `{}`, allowed values are `overwrite`, `reject` or `rename`", _value)))
+        }
+    }
+}
+
+/// Scanner of the uploaded objects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AntivirusMode {
+    /// The uploads are not scanned
+    Disabled,
+    /// ClamAV daemon listening on `unix:<socket path>` or `<host>:<port>`
+    Clamd(String),
+    /// Local scanner that only detects the EICAR test file, for development without ClamAV
+    Stub,
+}
+
+/// Key of the upload policy applied to the container types without their own policy
+pub const DEFAULT_UPLOAD_POLICY: &str = "*";
+
+/// Restrictions for the uploads of a container type, read from `UPLOAD_POLICY_FILE`
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct UploadPolicy {
+    /// Max size in bytes
+    pub max_size: Option<usize>,
+    /// Content types like `application/pdf`, or `image/*` for all the subtypes, empty or missing allows any type
+    pub allowed_content_types: Option<Vec<String>>,
+}
+
+impl UploadPolicy {
+    pub fn is_content_type_allowed(&self, _content_type: &str) -> bool {
+        let _allowed_content_types = match &self.allowed_content_types {
+            Some(value) if !value.is_empty() => value,
+            //  Without restriction, the same of the presigned POST
+            _ => return true,
+        };
+        //  Parameters like `; charset=utf-8` are not part of the type
+        let _content_type = _content_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+        _allowed_content_types.iter().any(|_allowed| {
+            let _allowed = _allowed.trim().to_lowercase();
+            match _allowed.strip_suffix('*') {
+                Some(_prefix) => _content_type.starts_with(_prefix),
+                None => _content_type.eq(&_allowed),
+            }
+        })
+    }
+
+    /// Validate the declared size and content type of an upload
+    pub fn validate(&self, _content_type: Option<&str>, _size: Option<usize>) -> Result<(), GatewayError> {
+        if let (Some(_max_size), Some(_size)) = (self.max_size, _size) {
+            if _size > _max_size {
+                log::error!("Upload size {} exceeds {}", _size, _max_size);
+                return Err(GatewayError::PayloadTooLarge(format!("Files larger than {} bytes are not allowed", _max_size)))
+            }
+        }
+        if let Some(_allowed_content_types) = &self.allowed_content_types {
+            let _content_type = _content_type.unwrap_or_default();
+            if !self.is_content_type_allowed(_content_type) {
+                log::error!("Content Type {:?} is not allowed", _content_type);
+                return Err(GatewayError::UnsupportedMediaType(format!("Content Type `{}` is not allowed, allowed types are `{}`", _content_type, _allowed_content_types.join("`, `"))))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Gateway settings read once from the environment at startup
+#[derive(Debug, Clone)]
+pub struct GatewayConfig {
+    pub port: String,
+    pub allowed_origin: String,
+    pub version: String,
+    pub s3_url: String,
+    pub bucket_name: String,
+    pub api_key: String,
+    pub secret_key: String,
+    pub manage_https: bool,
+    pub ssl_cert_file: Option<String>,
+    pub download_mode: DownloadMode,
+    pub collision_policy: CollisionPolicy,
+    pub delete_mode: DeleteMode,
+    pub trash_retention_days: u32,
+    pub upload_policies: HashMap<String, UploadPolicy>,
+    pub reject_content_type_mismatch: bool,
+    pub antivirus_mode: AntivirusMode,
+    pub auth_api_tokens: Vec<String>,
+    pub auth_jwt_secret: Option<String>,
+    pub auth_jwks_file: Option<String>,
+    pub auth_jwt_issuer: Option<String>,
+    pub auth_jwt_audience: Option<String>,
+    pub auth_client_claim: String,
+    pub auth_user_claim: String,
+    pub auth_role_claim: String,
+    pub auth_admin_claim: String,
+}
+
+impl GatewayConfig {
+    /// Load and validate the configuration, missing S3 settings are reported as error
+    pub fn from_env() -> Result<Self, GatewayError> {
+        let _port = get_optional_variable("PORT").unwrap_or_else(|| {
+            log::info!("Variable `PORT` Not found from enviroment, as default 7878");
+            "7878".to_owned()
+        });
+        // TODO: Add support to allow requests from multiple origin
+        let _allowed_origin = get_optional_variable("ALLOWED_ORIGIN").unwrap_or_else(|| {
+            log::warn!("Variable `ALLOWED_ORIGIN` Not found from enviroment");
+            "*".to_owned()
+        });
+        let _version = get_optional_variable("VERSION").unwrap_or_else(|| {
+            log::info!("Variable `VERSION` Not found from enviroment, as default `1.0.0-dev`");
+            "1.0.0-dev".to_owned()
+        });
+        let _manage_https = match get_optional_variable("MANAGE_HTTPS") {
+            Some(value) => value.eq("Y"),
+            None => {
+                log::info!("Variable `MANAGE_HTTPS` Not found");
+                false
+            }
+        };
+        let _ssl_cert_file = get_optional_variable("SSL_CERT_FILE");
+        if _manage_https && _ssl_cert_file.is_none() {
+            log::info!("Variable `SSL_CERT_FILE` Not found, using system certificates");
+        }
+        let _download_mode = match get_optional_variable("DOWNLOAD_MODE") {
+            Some(value) => match value.to_lowercase().as_str() {
+                "redirect" => DownloadMode::Redirect,
+                "proxy" => DownloadMode::Proxy,
+                _ => {
+                    log::error!("Invalid `DOWNLOAD_MODE` {:?}, allowed values are `redirect` or `proxy`", value);
+                    return Err(GatewayError::Config(format!("Invalid `DOWNLOAD_MODE`: {}", value)))
+                }
+            },
+            None => {
+                log::info!("Variable `DOWNLOAD_MODE` Not found from enviroment, as default `redirect`");
+                DownloadMode::Redirect
+            }
+        };
+        let _collision_policy = match get_optional_variable("UPLOAD_COLLISION_POLICY") {
+            Some(value) => match CollisionPolicy::from_str(&value) {
+                Ok(policy) => policy,
+                Err(error) => {
+                    log::error!("Invalid `UPLOAD_COLLISION_POLICY` {:?}, allowed values are `overwrite`, `reject` or `rename`", value);
+                    return Err(GatewayError::Config(error.to_string()))
+                }
+            },
+            None => {
+                log::info!("Variable `UPLOAD_COLLISION_POLICY` Not found from enviroment, as default `overwrite`");
+                CollisionPolicy::Overwrite
+            }
+        };
+        let _delete_mode = match get_optional_variable("DELETE_MODE") {
+            Some(value) => match value.to_lowercase().as_str() {
+                "permanent" => DeleteMode::Permanent,
+                "trash" => DeleteMode::Trash,
+                _ => {
+                    log::error!("Invalid `DELETE_MODE` {:?}, allowed values are `permanent` or `trash`", value);
+                    return Err(GatewayError::Config(format!("Invalid `DELETE_MODE`: {}", value)))
+                }
+            },
+            None => {
+                log::info!("Variable `DELETE_MODE` Not found from enviroment, as default `permanent`");
+                DeleteMode::Permanent
+            }
+        };
+        let _trash_retention_days = match get_optional_variable("TRASH_RETENTION_DAYS") {
+            Some(value) => match value.trim().parse::<u32>() {
+                Ok(days) if days > 0 => days,
+                _ => {
+                    log::error!("Invalid `TRASH_RETENTION_DAYS` {:?}, it must be a positive number of days", value);
+                    return Err(GatewayError::Config(format!("Invalid `TRASH_RETENTION_DAYS`: {}", value)))
+                }
+            },
+            None => 30,
+        };
+        let _upload_policies = match get_optional_variable("UPLOAD_POLICY_FILE") {
+            Some(_file) => {
+                let _content = std::fs::read_to_string(&_file).map_err(|error| {
+                    log::error!("Error reading `UPLOAD_POLICY_FILE` {:?}: {}", _file, error);
+                    GatewayError::Config(format!("Error reading `UPLOAD_POLICY_FILE`: {}", error))
+                })?;
+                let _policies = serde_json::from_str::<HashMap<String, UploadPolicy>>(&_content).map_err(|error| {
+                    log::error!("Invalid `UPLOAD_POLICY_FILE` {:?}: {}", _file, error);
+                    GatewayError::Config(format!("Invalid `UPLOAD_POLICY_FILE`: {}", error))
+                })?;
+                //  Container types are stored in lowercase, the keys are matched the same way
+                let mut _upload_policies: HashMap<String, UploadPolicy> = HashMap::new();
+                for (_container_type, _policy) in _policies {
+                    let _key = _container_type.trim().to_lowercase();
+                    if _upload_policies.insert(_key.to_owned(), _policy).is_some() {
+                        log::error!("Invalid `UPLOAD_POLICY_FILE` {:?}: policy `{}` is repeated", _file, _key);
+                        return Err(GatewayError::Config(format!("Invalid `UPLOAD_POLICY_FILE`: policy `{}` is repeated", _key)))
+                    }
+                }
+                _upload_policies
+            },
+            None => HashMap::new(),
+        };
+        let _antivirus_mode = match get_optional_variable("ANTIVIRUS_MODE") {
+            Some(value) => match value.to_lowercase().as_str() {
+                "disabled" => AntivirusMode::Disabled,
+                "clamd" => AntivirusMode::Clamd(get_optional_variable("CLAMD_ADDRESS").unwrap_or_else(|| {
+                    log::info!("Variable `CLAMD_ADDRESS` Not found from enviroment, as default `localhost:3310`");
+                    "localhost:3310".to_owned()
+                })),
+                "stub" => {
+                    log::warn!("Antivirus stub only detects the EICAR test file, use `clamd` in production");
+                    AntivirusMode::Stub
+                },
+                _ => {
+                    log::error!("Invalid `ANTIVIRUS_MODE` {:?}, allowed values are `disabled`, `clamd` or `stub`", value);
+                    return Err(GatewayError::Config(format!("Invalid `ANTIVIRUS_MODE`: {}", value)))
+                }
+            },
+            None => {
+                log::info!("Variable `ANTIVIRUS_MODE` Not found from enviroment, as default `disabled`");
+                AntivirusMode::Disabled
+            }
+        };
+        let _config = GatewayConfig {
+            port: _port,
+            allowed_origin: _allowed_origin,
+            version: _version,
+            s3_url: get_mandatory_variable("S3_URL")?,
+            bucket_name: get_mandatory_variable("BUCKET_NAME")?,
+            api_key: get_mandatory_variable("API_KEY")?,
+            secret_key: get_mandatory_variable("SECRET_KEY")?,
+            manage_https: _manage_https,
+            ssl_cert_file: _ssl_cert_file,
+            download_mode: _download_mode,
+            collision_policy: _collision_policy,
+            delete_mode: _delete_mode,
+            trash_retention_days: _trash_retention_days,
+            upload_policies: _upload_policies,
+            reject_content_type_mismatch: get_optional_variable("UPLOAD_REJECT_TYPE_MISMATCH").is_some_and(|value| value.eq("Y")),
+            antivirus_mode: _antivirus_mode,
+            auth_api_tokens: get_optional_variable("AUTH_API_TOKENS")
+                .map(|value| value.split(',').map(|token| token.trim().to_owned()).filter(|token| !token.is_empty()).collect())
+                .unwrap_or_default(),
+            auth_jwt_secret: get_optional_variable("AUTH_JWT_SECRET"),
+            auth_jwks_file: get_optional_variable("AUTH_JWKS_FILE"),
+            auth_jwt_issuer: get_optional_variable("AUTH_JWT_ISSUER"),
+            auth_jwt_audience: get_optional_variable("AUTH_JWT_AUDIENCE"),
+            auth_client_claim: get_optional_variable("AUTH_CLIENT_CLAIM").unwrap_or("AD_Client_ID".to_owned()),
+            auth_user_claim: get_optional_variable("AUTH_USER_CLAIM").unwrap_or("AD_User_ID".to_owned()),
+            auth_role_claim: get_optional_variable("AUTH_ROLE_CLAIM").unwrap_or("AD_Role_ID".to_owned()),
+            auth_admin_claim: get_optional_variable("AUTH_ADMIN_CLAIM").unwrap_or("is_admin".to_owned()),
+        };
+        //  Validate S3 URL before accept requests
+        _config.get_base_url()?;
+        Ok(_config)
+    }
+
+    /// Upload policy of the container type, or the `*` policy
+    pub fn get_upload_policy(&self, _container_type: Option<&str>) -> Option<&UploadPolicy> {
+        _container_type.and_then(|_container_type| self.upload_policies.get(&_container_type.to_lowercase()))
+            .or_else(|| self.upload_policies.get(DEFAULT_UPLOAD_POLICY))
+    }
+
+    /// URL of the bucket used as action of the presigned POST forms
+    pub fn get_bucket_url(&self, _region: &String) -> Result<String, GatewayError> {
+        let _url = self.get_base_url()?.build_url(&http::Method::POST, _region, &minio::s3::utils::Multimap::new(), Some(&self.bucket_name), None)?;
+        Ok(_url.to_string())
+    }
+
+    fn get_base_url(&self) -> Result<BaseUrl, GatewayError> {
+        let mut _base_url: BaseUrl = match BaseUrl::from_str(&self.s3_url) {
+            Ok(url) => url,
+            Err(error) => {
+                log::error!("Invalid `S3_URL` {:?}: {}", self.s3_url, error);
+                return Err(GatewayError::Config(format!("Invalid `S3_URL`: {}", error)))
+            }
+        };
+        _base_url.https = self.manage_https;
+        Ok(_base_url)
+    }
+
+    /// Build the S3 client shared by all requests
+    pub fn build_client(&self) -> Result<Client, GatewayError> {
+        let _base_url = self.get_base_url()?;
+        let _cert_file_path = match self.manage_https {
+            true => self.ssl_cert_file.as_deref().map(Path::new),
+            false => None,
+        };
+        let static_provider: StaticProvider = StaticProvider::new(
+            &self.api_key,
+            &self.secret_key, None);
+
+        match Client::new(_base_url, Some(Box::new(static_provider)), _cert_file_path, None) {
+            Ok(client) => Ok(client),
+            Err(error) => {
+                log::error!("Error creating S3 client: {}", error);
+                Err(GatewayError::Config(format!("Error creating S3 client: {}", error)))
+            }
+        }
+    }
+
+    /// Build the HTTP client used to stream bodies through presigned URLs
+    pub fn build_http_client(&self) -> Result<reqwest::Client, GatewayError> {
+        let mut _builder = reqwest::Client::builder().no_gzip();
+        if self.manage_https {
+            if let Some(_ssl_cert_file) = &self.ssl_cert_file {
+                let _certificate = std::fs::read(_ssl_cert_file)
+                    .map_err(|error| error.to_string())
+                    .and_then(|_buffer| reqwest::Certificate::from_pem(&_buffer).map_err(|error| error.to_string()));
+                match _certificate {
+                    Ok(certificate) => _builder = _builder.add_root_certificate(certificate),
+                    Err(error) => {
+                        log::error!("Error loading `SSL_CERT_FILE`: {}", error);
+                        return Err(GatewayError::Config(format!("Error loading `SSL_CERT_FILE`: {}", error)))
+                    }
+                }
+            }
+        }
+        match _builder.build() {
+            Ok(client) => Ok(client),
+            Err(error) => {
+                log::error!("Error creating HTTP client: {}", error);
+                Err(GatewayError::Config(format!("Error creating HTTP client: {}", error)))
+            }
+        }
+    }
+}
+
+fn get_optional_variable(_name: &str) -> Option<String> {
+    match env::var(_name) {
+        Ok(value) if !value.trim().is_empty() => Some(value),
+        _ => None,
+    }
+}
+
+fn get_mandatory_variable(_name: &str) -> Result<String, GatewayError> {
+    match get_optional_variable(_name) {
+        Some(value) => Ok(value),
+        None => {
+            log::error!("Variable `{}` is Mandatory", _name);
+            Err(GatewayError::Config(format!("Variable `{}` is Mandatory", _name)))
+        }
+    }
+}
+
+/// Shared configuration injected into the depot by the server
+pub fn get_config(_depot: &Depot) -> Arc<GatewayConfig> {
+    _depot.obtain::<Arc<GatewayConfig>>()
+        .expect("Gateway configuration is not injected")
+        .clone()
+}
+
+/// Shared S3 client injected into the depot by the server
+pub fn get_client(_depot: &Depot) -> Arc<Client> {
+    _depot.obtain::<Arc<Client>>()
+        .expect("S3 client is not injected")
+        .clone()
+}
+
+/// Shared HTTP client injected into the depot by the server
+pub fn get_http_client(_depot: &Depot) -> reqwest::Client {
+    _depot.obtain::<reqwest::Client>()
+        .expect("HTTP client is not injected")
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_policy(_allowed_content_types: Option<Vec<&str>>) -> UploadPolicy {
+        UploadPolicy {
+            max_size: Some(10),
+            allowed_content_types: _allowed_content_types.map(|_values| _values.into_iter().map(|_value| _value.to_owned()).collect()),
+        }
+    }
+
+    #[test]
+    fn missing_or_empty_list_allows_any_type() {
+        for _policy in [get_policy(None), get_policy(Some(Vec::new()))] {
+            assert!(_policy.is_content_type_allowed("application/x-msdownload"));
+            assert!(_policy.validate(Some("application/x-msdownload"), Some(10)).is_ok());
+            assert!(_policy.validate(None, None).is_ok());
+        }
+    }
+
+    #[test]
+    fn listed_types_and_subtypes_are_allowed() {
+        let _policy = get_policy(Some(vec!["application/pdf", "image/*"]));
+        assert!(_policy.is_content_type_allowed("application/pdf"));
+        assert!(_policy.is_content_type_allowed("Image/PNG; charset=binary"));
+        assert!(!_policy.is_content_type_allowed("text/plain"));
+        assert!(matches!(_policy.validate(Some("text/plain"), None), Err(GatewayError::UnsupportedMediaType(_))));
+        assert!(matches!(_policy.validate(None, None), Err(GatewayError::UnsupportedMediaType(_))));
+        assert!(matches!(_policy.validate(Some("application/pdf"), Some(11)), Err(GatewayError::PayloadTooLarge(_))));
+    }
+}
@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use futures_util::future::Either;
+use futures_util::{Stream, StreamExt};
+use minio::s3::args::DEFAULT_EXPIRY_SECONDS;
+use minio::s3::client::Client;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::config::{AntivirusMode, GatewayConfig};
+use crate::controller::metadata::{get_replace_headers, get_user_metadata_value, ObjectMetadata, QUARANTINE_ORIGIN_KEY, SCANNED_AT_KEY, SCAN_SIGNATURE_KEY, SCAN_STATUS_KEY};
+use crate::controller::s3::{copy_object, delete_object, get_client_folder_file_name, get_object_stat, get_object_stream, get_quoted_etag, get_resource_path, list_client_folders, put_empty_object, validate_copy_size, Resource};
+use crate::error::GatewayError;
+
+/// Folder of each client where the infected objects are kept
+pub const QUARANTINE_FOLDER: &str = "quarantine";
+/// Folder of each client with an empty marker by upload made without the gateway, the job only checks these keys
+pub const PENDING_FOLDER: &str = "pending";
+/// Time between runs of the job that scans the uploads made with presigned URLs
+pub const SCAN_PENDING_INTERVAL_SECONDS: u64 = 300;
+/// Values of the `scan-status` metadata
+pub const SCAN_STATUS_PENDING: &str = "pending";
+pub const SCAN_STATUS_CLEAN: &str = "clean";
+pub const SCAN_STATUS_INFECTED: &str = "infected";
+
+/// Test file of the antivirus vendors, the only threat known by the stub scanner
+const EICAR_SIGNATURE: &[u8] = b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+const EICAR_SIGNATURE_NAME: &str = "Eicar-Test-Signature";
+/// Size of the `INSTREAM` chunks, clamd rejects the chunks larger than its `StreamMaxLength`
+const CLAMD_CHUNK_SIZE: usize = 65536;
+/// Time to wait for each connect, write and reply of clamd, a hung daemon must not hold the uploads
+const CLAMD_TIMEOUT_SECONDS: u64 = 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanResult {
+    Clean,
+    /// Name of the detected signature
+    Infected(String),
+}
+
+trait ClamdConnection: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin> ClamdConnection for T {}
+
+enum Scanner {
+    Clamd(Box<dyn ClamdConnection>),
+    /// The end of the previous chunk is kept, the signature can be split between chunks
+    Stub { tail: Vec<u8>, is_infected: bool },
+}
+
+/// Scan in progress, the content is sent by chunks while it is read
+pub struct ScanSession {
+    scanner: Scanner,
+}
+
+fn get_clamd_error(error: std::io::Error) -> GatewayError {
+    log::error!("Error scanning with clamd: {}", error);
+    GatewayError::Upstream(format!("Antivirus is not available: {}", error))
+}
+
+async fn with_clamd_timeout<T>(_future: impl std::future::Future<Output = Result<T, std::io::Error>>) -> Result<T, GatewayError> {
+    match tokio::time::timeout(std::time::Duration::from_secs(CLAMD_TIMEOUT_SECONDS), _future).await {
+        Ok(_result) => _result.map_err(get_clamd_error),
+        Err(_) => {
+            log::error!("Error scanning with clamd: no answer in {} seconds", CLAMD_TIMEOUT_SECONDS);
+            Err(GatewayError::Upstream(format!("Antivirus did not answer in {} seconds", CLAMD_TIMEOUT_SECONDS)))
+        }
+    }
+}
+
+/// `unix:<socket path>` or `<host>:<port>` of clamd
+async fn connect_clamd(_address: &str) -> Result<Box<dyn ClamdConnection>, std::io::Error> {
+    match _address.strip_prefix("unix:") {
+        Some(_path) => Ok(Box::new(tokio::net::UnixStream::connect(_path.trim_start_matches("//")).await?)),
+        None => Ok(Box::new(tokio::net::TcpStream::connect(_address.trim_start_matches("tcp://")).await?)),
+    }
+}
+
+/// Reply of `INSTREAM`, like `stream: OK` or `stream: Win.Test.EICAR_HDB-1 FOUND`
+fn parse_clamd_reply(_reply: &str) -> Result<ScanResult, GatewayError> {
+    let _reply = _reply.trim_end_matches('\0').trim();
+    let _result = _reply.strip_prefix("stream:").unwrap_or(_reply).trim();
+    if _result.eq("OK") {
+        return Ok(ScanResult::Clean)
+    }
+    match _result.strip_suffix("FOUND").map(|_signature| _signature.trim()) {
+        Some(_signature) if !_signature.is_empty() => Ok(ScanResult::Infected(_signature.to_owned())),
+        _ => {
+            log::error!("Error scanning with clamd: {:?}", _reply);
+            Err(GatewayError::Upstream(format!("Antivirus error: {}", _result)))
+        }
+    }
+}
+
+impl ScanSession {
+    /// Start a scan with the configured antivirus, `None` when the scan is disabled
+    pub async fn start(_mode: &AntivirusMode) -> Result<Option<Self>, GatewayError> {
+        let _scanner = match _mode {
+            AntivirusMode::Disabled => return Ok(None),
+            AntivirusMode::Clamd(_address) => {
+                let mut _connection = with_clamd_timeout(connect_clamd(_address)).await?;
+                //  `z` commands end with a NULL character
+                with_clamd_timeout(_connection.write_all(b"zINSTREAM\0")).await?;
+                Scanner::Clamd(_connection)
+            },
+            AntivirusMode::Stub => Scanner::Stub {
+                tail: Vec::new(),
+                is_infected: false,
+            },
+        };
+        Ok(Some(ScanSession {
+            scanner: _scanner,
+        }))
+    }
+
+    pub async fn write(&mut self, _chunk: &[u8]) -> Result<(), GatewayError> {
+        match &mut self.scanner {
+            Scanner::Clamd(_connection) => {
+                //  Each chunk is sent with its length as 4 bytes in network order
+                for _part in _chunk.chunks(CLAMD_CHUNK_SIZE) {
+                    with_clamd_timeout(_connection.write_all(&(_part.len() as u32).to_be_bytes())).await?;
+                    with_clamd_timeout(_connection.write_all(_part)).await?;
+                }
+            },
+            Scanner::Stub { tail, is_infected } => {
+                tail.extend_from_slice(_chunk);
+                if tail.windows(EICAR_SIGNATURE.len()).any(|_window| _window.eq(EICAR_SIGNATURE)) {
+                    *is_infected = true;
+                }
+                let _consumed = tail.len().saturating_sub(EICAR_SIGNATURE.len() - 1);
+                tail.drain(.._consumed);
+            },
+        }
+        Ok(())
+    }
+
+    /// End the content and wait for the result
+    pub async fn finish(self) -> Result<ScanResult, GatewayError> {
+        match self.scanner {
+            Scanner::Clamd(mut _connection) => {
+                //  A chunk of length zero ends the stream, clamd closes the connection after the reply
+                with_clamd_timeout(_connection.write_all(&[0, 0, 0, 0])).await?;
+                let mut _reply = Vec::new();
+                with_clamd_timeout(_connection.read_to_end(&mut _reply)).await?;
+                parse_clamd_reply(&String::from_utf8_lossy(&_reply))
+            },
+            Scanner::Stub { is_infected, .. } => Ok(match is_infected {
+                true => ScanResult::Infected(EICAR_SIGNATURE_NAME.to_owned()),
+                false => ScanResult::Clean,
+            }),
+        }
+    }
+}
+
+struct ScanStreamState<S> {
+    stream: std::pin::Pin<Box<S>>,
+    session: Option<ScanSession>,
+    last_chunk: Option<Bytes>,
+    failure: Arc<Mutex<Option<GatewayError>>>,
+}
+
+/// Keep the error to answer it instead of the error of the aborted upload
+fn set_failure(_failure: &Mutex<Option<GatewayError>>, _error: GatewayError) -> std::io::Error {
+    let _message = _error.to_string();
+    if let Ok(mut _failure) = _failure.lock() {
+        *_failure = Some(_error);
+    }
+    std::io::Error::other(_message)
+}
+
+/// Scan an upload body while it is sent to S3, the last chunk is held until the scan ends so the upload of
+/// an infected body is aborted before S3 completes the object
+fn scan_stream<S>(_session: ScanSession, _stream: S, _failure: Arc<Mutex<Option<GatewayError>>>) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync + 'static
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync + 'static,
+{
+    let _state = ScanStreamState {
+        stream: Box::pin(_stream),
+        session: Some(_session),
+        last_chunk: None,
+        failure: _failure,
+    };
+    futures_util::stream::try_unfold(_state, |mut _state| async move {
+        loop {
+            let _session = match _state.session.as_mut() {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+            match _state.stream.next().await {
+                Some(Ok(_chunk)) => {
+                    _session.write(&_chunk).await.map_err(|error| set_failure(&_state.failure, error))?;
+                    if let Some(_previous_chunk) = _state.last_chunk.replace(_chunk) {
+                        return Ok(Some((_previous_chunk, _state)))
+                    }
+                },
+                Some(Err(error)) => return Err(error),
+                None => {
+                    let _result = match _state.session.take() {
+                        Some(_session) => _session.finish().await,
+                        None => return Ok(None),
+                    };
+                    return match _result {
+                        Ok(ScanResult::Clean) => {
+                            let _last_chunk = _state.last_chunk.take();
+                            Ok(_last_chunk.map(|_chunk| (_chunk, _state)))
+                        },
+                        Ok(ScanResult::Infected(_signature)) => {
+                            log::warn!("Threat `{}` found in an upload", _signature);
+                            Err(set_failure(&_state.failure, GatewayError::Infected(format!("Threat `{}` found in the file", _signature))))
+                        },
+                        Err(error) => Err(set_failure(&_state.failure, error)),
+                    }
+                },
+            }
+        }
+    })
+}
+
+/// Scan the body of an upload through the gateway, the metadata is marked as clean because an infected body is never stored.
+/// The error of the scan is set on `_failure` when the upload is aborted
+pub async fn scan_upload_stream<S>(_config: &GatewayConfig, _metadata: &ObjectMetadata, _stream: S, _failure: Arc<Mutex<Option<GatewayError>>>) -> Result<(ObjectMetadata, impl Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync + 'static), GatewayError>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync + 'static,
+{
+    let mut _metadata = _metadata.to_owned();
+    match ScanSession::start(&_config.antivirus_mode).await? {
+        Some(_session) => {
+            _metadata.insert_user_metadata(SCAN_STATUS_KEY, Some(SCAN_STATUS_CLEAN.to_owned()));
+            _metadata.insert_user_metadata(SCANNED_AT_KEY, Some(Utc::now().to_rfc3339()));
+            Ok((_metadata, Either::Left(scan_stream(_session, _stream, _failure))))
+        },
+        None => Ok((_metadata, Either::Right(_stream))),
+    }
+}
+
+/// Uploads with presigned URLs do not pass through the gateway, they are scanned later
+pub fn insert_pending_scan_status(_config: &GatewayConfig, _metadata: &mut ObjectMetadata) {
+    if _config.antivirus_mode != AntivirusMode::Disabled {
+        _metadata.insert_user_metadata(SCAN_STATUS_KEY, Some(SCAN_STATUS_PENDING.to_owned()));
+    }
+}
+
+fn is_scan_pending(_user_metadata: Option<&HashMap<String, String>>) -> bool {
+    get_user_metadata_value(_user_metadata, SCAN_STATUS_KEY).is_some_and(|_status| _status.eq(SCAN_STATUS_PENDING))
+}
+
+/// Marker `<client>/pending/<key without client>` of an upload to scan, it is written when the metadata is `pending`
+pub async fn put_pending_scan_marker(_client: &Client, _config: &GatewayConfig, _file_name: &str, _user_metadata: Option<&HashMap<String, String>>) -> Result<(), GatewayError> {
+    if !is_scan_pending(_user_metadata) {
+        return Ok(())
+    }
+    let _marker_file_name = get_client_folder_file_name(_file_name, PENDING_FOLDER)?;
+    put_empty_object(_client, _config, &_marker_file_name).await
+}
+
+/// Object key of a pending marker
+fn get_pending_file_name(_marker_file_name: &str) -> Option<String> {
+    let (_client_id, _path) = _marker_file_name.split_once('/')?;
+    let _path = _path.strip_prefix(PENDING_FOLDER)?.strip_prefix('/')?;
+    match _client_id.is_empty() || _path.is_empty() {
+        true => None,
+        false => Some(format!("{}/{}", _client_id, _path)),
+    }
+}
+
+/// Quarantine key `<client>/quarantine/<scanned at millis>/<key without client>`
+pub fn get_quarantine_file_name(_file_name: &str, _scanned_at: DateTime<Utc>) -> Result<String, GatewayError> {
+    get_client_folder_file_name(_file_name, &format!("{}/{}", QUARANTINE_FOLDER, _scanned_at.timestamp_millis()))
+}
+
+/// Key and metadata of an object after the scan, the clean objects keep their key and the infected ones go to quarantine
+fn get_scanned_object(_file_name: &str, _user_metadata: &HashMap<String, String>, _result: &ScanResult, _scanned_at: DateTime<Utc>) -> Result<(String, HashMap<String, String>), GatewayError> {
+    let mut _user_metadata = _user_metadata.to_owned();
+    _user_metadata.insert(SCANNED_AT_KEY.to_owned(), _scanned_at.to_rfc3339());
+    match _result {
+        ScanResult::Clean => {
+            _user_metadata.insert(SCAN_STATUS_KEY.to_owned(), SCAN_STATUS_CLEAN.to_owned());
+            Ok((_file_name.to_owned(), _user_metadata))
+        },
+        ScanResult::Infected(_signature) => {
+            _user_metadata.insert(SCAN_STATUS_KEY.to_owned(), SCAN_STATUS_INFECTED.to_owned());
+            _user_metadata.insert(SCAN_SIGNATURE_KEY.to_owned(), _signature.to_owned());
+            _user_metadata.insert(QUARANTINE_ORIGIN_KEY.to_owned(), _file_name.to_owned());
+            Ok((get_quarantine_file_name(_file_name, _scanned_at)?, _user_metadata))
+        },
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ScanResponse {
+    pub file_name: String,
+    /// `clean` or `infected`
+    pub scan_status: String,
+    pub signature: Option<String>,
+    /// Object after the scan, the infected objects are in the quarantine folder
+    pub resource: Resource,
+}
+
+/// Scan a stored object, the status is saved in its metadata and the infected objects are moved to the quarantine of the client
+pub async fn scan_object(_client: &Client, _http_client: &reqwest::Client, _config: &GatewayConfig, _file_name: String) -> Result<ScanResponse, GatewayError> {
+    get_resource_path(&_file_name)?;
+    let mut _session = match ScanSession::start(&_config.antivirus_mode).await? {
+        Some(value) => value,
+        None => {
+            log::error!("Scan of `{}` requested with the antivirus disabled", _file_name);
+            return Err(GatewayError::Config("Antivirus is disabled, set `ANTIVIRUS_MODE` to scan the files".to_owned()))
+        }
+    };
+    let _stat = get_object_stat(_client, _config, _file_name.to_owned(), None).await?;
+    //  The result is saved with a copy, it is rejected before download the object
+    validate_copy_size(&_stat)?;
+    //  The scanned content must be the one of the stat, the result is saved with a copy that requires the same ETag
+    let _etag = get_quoted_etag(&_stat.etag);
+    let _response = get_object_stream(_client, _http_client, _config, _file_name.to_owned(), None, vec![(http::header::IF_MATCH.to_string(), _etag.to_owned())]).await?;
+    let mut _body = _response.bytes_stream();
+    while let Some(_chunk) = _body.next().await {
+        match _chunk {
+            Ok(_chunk) => _session.write(&_chunk).await?,
+            Err(error) => {
+                log::error!("Error downloading `{}` to scan: {}", _file_name, error);
+                return Err(GatewayError::Upstream(format!("Error downloading `{}`: {}", _file_name, error)))
+            }
+        }
+    }
+    let _result = _session.finish().await?;
+    let (_scanned_file_name, _user_metadata) = get_scanned_object(&_file_name, &_stat.user_metadata, &_result, Utc::now())?;
+    let _headers = get_replace_headers(&_stat, _user_metadata);
+    copy_object(_client, _config, &_stat, &_scanned_file_name, Some(_headers)).await?;
+    let (_scan_status, _signature) = match _result {
+        ScanResult::Clean => (SCAN_STATUS_CLEAN, None),
+        ScanResult::Infected(_signature) => {
+            log::warn!("Threat `{}` found in `{}`, moved to `{}`", _signature, _file_name, _scanned_file_name);
+            //  An upload made after the copy is not deleted, it is scanned on its own
+            match get_object_stat(_client, _config, _file_name.to_owned(), None).await {
+                Ok(_current) if get_quoted_etag(&_current.etag).eq(&_etag) => delete_object(_client, _config, _file_name.to_owned()).await?,
+                Ok(_) => log::warn!("`{}` was uploaded again after the scan, it is not deleted", _file_name),
+                Err(GatewayError::NotFound(_)) => {},
+                Err(error) => return Err(error),
+            }
+            (SCAN_STATUS_INFECTED, Some(_signature))
+        },
+    };
+    let _stat = get_object_stat(_client, _config, _scanned_file_name, None).await?;
+    Ok(ScanResponse {
+        file_name: _file_name,
+        scan_status: _scan_status.to_owned(),
+        signature: _signature,
+        resource: Resource::from_stat(&_stat),
+    })
+}
+
+/// Scan the objects with a pending marker, the markers are kept until the presigned URLs expire because
+/// a URL can upload the object again, so only the objects with the `pending` status are scanned
+pub async fn scan_pending_objects(_client: &Client, _http_client: &reqwest::Client, _config: &GatewayConfig) -> Result<usize, GatewayError> {
+    let _expiration = Utc::now() - Duration::seconds(DEFAULT_EXPIRY_SECONDS.into());
+    let mut _scanned = 0;
+    for _marker in list_client_folders(_client, _config, PENDING_FOLDER).await? {
+        //  Only the containers are scanned
+        let _file_name = match get_pending_file_name(&_marker.name).filter(|_file_name| get_resource_path(_file_name).is_ok()) {
+            Some(value) => value,
+            None => continue,
+        };
+        let _is_pending = match get_object_stat(_client, _config, _file_name.to_owned(), None).await {
+            Ok(_stat) => is_scan_pending(Some(&_stat.user_metadata)),
+            //  Not uploaded yet
+            Err(GatewayError::NotFound(_)) => false,
+            Err(error) => {
+                log::warn!("Error reading `{}` to scan: {}", _file_name, error);
+                continue
+            }
+        };
+        if _is_pending {
+            match scan_object(_client, _http_client, _config, _file_name.to_owned()).await {
+                Ok(_) => _scanned += 1,
+                //  The object stays pending and it is retried on the next run, also when it was uploaded again during the scan
+                Err(error) => {
+                    log::warn!("Error scanning `{}`: {}", _file_name, error);
+                    continue
+                }
+            }
+        }
+        let _is_expired = _marker.last_modified.as_deref()
+            .and_then(|value| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").ok())
+            .is_some_and(|_last_modified| _last_modified.and_utc() < _expiration);
+        if _is_expired {
+            if let Err(error) = delete_object(_client, _config, _marker.name.to_owned()).await {
+                log::warn!("Error removing the pending marker `{}`: {}", _marker.name, error);
+            }
+        }
+    }
+    Ok(_scanned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE_NAME: &str = "11/client/attachment/c_order/1/file.txt";
+
+    fn get_scanned_at() -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(1700000000000).unwrap()
+    }
+
+    async fn scan_stub(_chunks: &[&[u8]]) -> ScanResult {
+        let mut _session = ScanSession::start(&AntivirusMode::Stub).await.unwrap().unwrap();
+        for _chunk in _chunks {
+            _session.write(_chunk).await.unwrap();
+        }
+        _session.finish().await.unwrap()
+    }
+
+    #[test]
+    fn parses_clamd_replies() {
+        assert_eq!(parse_clamd_reply("stream: OK\0").unwrap(), ScanResult::Clean);
+        assert_eq!(parse_clamd_reply("stream: Win.Test.EICAR_HDB-1 FOUND\0").unwrap(), ScanResult::Infected("Win.Test.EICAR_HDB-1".to_owned()));
+        assert_eq!(parse_clamd_reply("OK\n").unwrap(), ScanResult::Clean);
+    }
+
+    #[test]
+    fn rejects_clamd_errors_and_malformed_replies() {
+        for _reply in ["INSTREAM size limit exceeded. ERROR\0", "stream: FOUND\0", "", "\0", "stream: PONG"] {
+            assert!(matches!(parse_clamd_reply(_reply), Err(GatewayError::Upstream(_))), "{:?}", _reply);
+        }
+    }
+
+    #[tokio::test]
+    async fn stub_detects_eicar() {
+        assert_eq!(scan_stub(&[b"plain text", b" content"]).await, ScanResult::Clean);
+        assert_eq!(scan_stub(&[b"prefix ", EICAR_SIGNATURE, b" suffix"]).await, ScanResult::Infected(EICAR_SIGNATURE_NAME.to_owned()));
+        //  The signature split between chunks is detected too
+        let (_start, _end) = EICAR_SIGNATURE.split_at(20);
+        assert_eq!(scan_stub(&[b"prefix ", _start, _end, b" suffix"]).await, ScanResult::Infected(EICAR_SIGNATURE_NAME.to_owned()));
+        assert!(ScanSession::start(&AntivirusMode::Disabled).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn clamd_session_sends_instream_chunks() {
+        let _listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _address = _listener.local_addr().unwrap().to_string();
+        let _server = tokio::spawn(async move {
+            let (mut _connection, _) = _listener.accept().await.unwrap();
+            let mut _command = [0; 10];
+            _connection.read_exact(&mut _command).await.unwrap();
+            let mut _content = Vec::new();
+            loop {
+                let mut _length = [0; 4];
+                _connection.read_exact(&mut _length).await.unwrap();
+                let _length = u32::from_be_bytes(_length) as usize;
+                if _length == 0 {
+                    break
+                }
+                let mut _chunk = vec![0; _length];
+                _connection.read_exact(&mut _chunk).await.unwrap();
+                _content.extend_from_slice(&_chunk);
+            }
+            _connection.write_all(b"stream: Win.Test.EICAR_HDB-1 FOUND\0").await.unwrap();
+            (_command, _content)
+        });
+        let mut _session = ScanSession::start(&AntivirusMode::Clamd(_address)).await.unwrap().unwrap();
+        _session.write(&vec![1; CLAMD_CHUNK_SIZE + 1]).await.unwrap();
+        _session.write(b"end").await.unwrap();
+        assert_eq!(_session.finish().await.unwrap(), ScanResult::Infected("Win.Test.EICAR_HDB-1".to_owned()));
+        let (_command, _content) = _server.await.unwrap();
+        assert_eq!(&_command, b"zINSTREAM\0");
+        assert_eq!(_content.len(), CLAMD_CHUNK_SIZE + 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clamd_session_fails_without_reply() {
+        let _listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _address = _listener.local_addr().unwrap().to_string();
+        //  The connection is kept open without reply
+        let _server = tokio::spawn(async move {
+            let (mut _connection, _) = _listener.accept().await.unwrap();
+            let mut _content = Vec::new();
+            let _ = _connection.read_to_end(&mut _content).await;
+        });
+        let mut _session = ScanSession::start(&AntivirusMode::Clamd(_address)).await.unwrap().unwrap();
+        _session.write(b"content").await.unwrap();
+        assert!(matches!(_session.finish().await, Err(GatewayError::Upstream(_))));
+        _server.abort();
+    }
+
+    #[test]
+    fn builds_quarantine_file_name() {
+        assert_eq!(get_quarantine_file_name(FILE_NAME, get_scanned_at()).unwrap(), "11/quarantine/1700000000000/client/attachment/c_order/1/file.txt");
+        assert!(get_quarantine_file_name("file.txt", get_scanned_at()).is_err());
+    }
+
+    #[test]
+    fn keeps_clean_objects_in_place() {
+        let _user_metadata = HashMap::from([("display-name".to_owned(), "file.txt".to_owned()), (SCAN_STATUS_KEY.to_owned(), SCAN_STATUS_PENDING.to_owned())]);
+        let (_file_name, _user_metadata) = get_scanned_object(FILE_NAME, &_user_metadata, &ScanResult::Clean, get_scanned_at()).unwrap();
+        assert_eq!(_file_name, FILE_NAME);
+        assert_eq!(_user_metadata.get(SCAN_STATUS_KEY).unwrap(), SCAN_STATUS_CLEAN);
+        assert_eq!(_user_metadata.get(SCANNED_AT_KEY).unwrap(), &get_scanned_at().to_rfc3339());
+        assert_eq!(_user_metadata.get("display-name").unwrap(), "file.txt");
+        assert!(!_user_metadata.contains_key(SCAN_SIGNATURE_KEY));
+        assert!(!_user_metadata.contains_key(QUARANTINE_ORIGIN_KEY));
+    }
+
+    #[tokio::test]
+    async fn moves_infected_objects_to_quarantine() {
+        let _result = scan_stub(&[EICAR_SIGNATURE]).await;
+        let (_file_name, _user_metadata) = get_scanned_object(FILE_NAME, &HashMap::new(), &_result, get_scanned_at()).unwrap();
+        assert_eq!(_file_name, "11/quarantine/1700000000000/client/attachment/c_order/1/file.txt");
+        assert_eq!(_user_metadata.get(SCAN_STATUS_KEY).unwrap(), SCAN_STATUS_INFECTED);
+        assert_eq!(_user_metadata.get(SCAN_SIGNATURE_KEY).unwrap(), EICAR_SIGNATURE_NAME);
+        assert_eq!(_user_metadata.get(QUARANTINE_ORIGIN_KEY).unwrap(), FILE_NAME);
+    }
+}
@@ -0,0 +1,449 @@
+use std::collections::{HashSet, VecDeque};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use futures_util::{Stream, StreamExt};
+use minio::s3::client::Client;
+
+use crate::config::GatewayConfig;
+use crate::controller::s3::{get_object_stream, get_valid_path, list_objects_by_prefix, ListObjectsOptions, Resource};
+use crate::error::GatewayError;
+
+/// Values that do not fit in the ZIP headers and require the ZIP64 extension
+const ZIP64_LIMIT: u64 = 0xFFFFFFFF;
+const ZIP64_ENTRIES_LIMIT: u64 = 0xFFFF;
+/// Sizes and CRC are sent after the data and the names are UTF-8
+const ZIP_FLAGS: u16 = 0x0808;
+const ZIP_VERSION: u16 = 20;
+const ZIP64_VERSION: u16 = 45;
+
+/// Object of the container and its path inside the archive
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    pub file_name: String,
+    pub name: String,
+    pub size: u64,
+    pub last_modified: Option<NaiveDateTime>,
+}
+
+/// Archive of a container, the objects are listed before start the download
+#[derive(Debug, Clone)]
+pub struct ContainerArchive {
+    pub file_name: String,
+    pub entries: Vec<ArchiveEntry>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn get_container_archive(_client: &Client, _config: &GatewayConfig, _client_id: Option<String>, _container_id: Option<String>, _container_type: Option<String>, _table_name: Option<String>, _column_name: Option<String>, _record_id: Option<String>, _user_id: Option<String>, _role_id: Option<String>) -> Result<ContainerArchive, GatewayError> {
+    let _folder = get_valid_path(_client_id, _container_id, _container_type, _table_name, _column_name, _record_id, _user_id, _role_id, true)?;
+    let _prefix = format!("{}/", _folder);
+    let _objects = list_objects_by_prefix(_client, _config, Some(_prefix.to_owned()), ListObjectsOptions {
+        fetch_all: true,
+        ..ListObjectsOptions::default()
+    }).await?;
+    let _entries = get_archive_entries(&_prefix, _objects.resources.unwrap_or_default());
+    if _entries.is_empty() {
+        log::warn!("Folder `{}` has no objects", _prefix);
+        return Err(GatewayError::NotFound(format!("Folder `{}` does not exist", _prefix)))
+    }
+    let _archive_name = _folder.rsplit('/').next().unwrap_or(&_folder);
+    Ok(ContainerArchive {
+        file_name: format!("{}.zip", _archive_name),
+        entries: _entries,
+    })
+}
+
+/// Entries named with the display name under the sub folders of the container, repeated names get a numeric suffix
+fn get_archive_entries(_prefix: &str, _resources: Vec<Resource>) -> Vec<ArchiveEntry> {
+    let mut _names: HashSet<String> = HashSet::new();
+    let mut _entries: Vec<ArchiveEntry> = Vec::new();
+    for _resource in _resources.into_iter().filter(|_resource| !_resource.is_prefix && !_resource.is_delete_marker) {
+        let _path = _resource.name.strip_prefix(_prefix).unwrap_or(&_resource.name);
+        let _folder = match _path.rsplit_once('/') {
+            Some((_folder, _)) => format!("{}/", _folder),
+            None => String::new(),
+        };
+        let _display_name = _resource.display_name.to_owned().unwrap_or_else(|| _path.rsplit('/').next().unwrap_or(_path).to_owned());
+        //  The display name is sent by the users, it can not add folders to the archive
+        let _display_name = match _display_name.replace(['/', '\\'], "_") {
+            value if value.trim_matches('.').is_empty() => "_".to_owned(),
+            value => value,
+        };
+        let (_stem, _extension) = match _display_name.rfind('.') {
+            Some(index) if index > 0 => _display_name.split_at(index),
+            _ => (_display_name.as_str(), ""),
+        };
+        let mut _name = format!("{}{}", _folder, _display_name);
+        let mut _attempt = 1;
+        while !_names.insert(_name.to_lowercase()) {
+            _attempt += 1;
+            _name = format!("{}{}_{}{}", _folder, _stem, _attempt, _extension);
+        }
+        _entries.push(ArchiveEntry {
+            file_name: _resource.name.to_owned(),
+            name: _name,
+            size: _resource.size.unwrap_or(0) as u64,
+            last_modified: _resource.last_modified.as_deref().and_then(|value| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").ok()),
+        });
+    }
+    _entries
+}
+
+/// Entry being written, its central directory record is added when the data ends
+struct ZipEntry {
+    name: String,
+    offset: u64,
+    time: u16,
+    date: u16,
+    is_zip64: bool,
+}
+
+/// Writer of a ZIP without compression, the data is streamed between the headers built here
+#[derive(Default)]
+struct ZipWriter {
+    offset: u64,
+    entries: u64,
+    central_directory: BytesMut,
+    current: Option<ZipEntry>,
+}
+
+impl ZipWriter {
+    fn start_entry(&mut self, _entry: &ArchiveEntry) -> Bytes {
+        let (_time, _date) = get_dos_date_time(_entry.last_modified);
+        let _is_zip64 = _entry.size >= ZIP64_LIMIT;
+        let mut _header = BytesMut::new();
+        _header.put_u32_le(0x04034b50);
+        _header.put_u16_le(if _is_zip64 { ZIP64_VERSION } else { ZIP_VERSION });
+        _header.put_u16_le(ZIP_FLAGS);
+        _header.put_u16_le(0);
+        _header.put_u16_le(_time);
+        _header.put_u16_le(_date);
+        _header.put_u32_le(0);
+        //  The sizes are sent in the data descriptor, ZIP64 entries flag them on the extra field
+        let _size = if _is_zip64 { ZIP64_LIMIT as u32 } else { 0 };
+        _header.put_u32_le(_size);
+        _header.put_u32_le(_size);
+        _header.put_u16_le(_entry.name.len() as u16);
+        _header.put_u16_le(if _is_zip64 { 20 } else { 0 });
+        _header.put_slice(_entry.name.as_bytes());
+        if _is_zip64 {
+            _header.put_u16_le(0x0001);
+            _header.put_u16_le(16);
+            _header.put_u64_le(0);
+            _header.put_u64_le(0);
+        }
+        self.current = Some(ZipEntry {
+            name: _entry.name.to_owned(),
+            offset: self.offset,
+            time: _time,
+            date: _date,
+            is_zip64: _is_zip64,
+        });
+        self.offset += _header.len() as u64;
+        _header.freeze()
+    }
+
+    fn finish_entry(&mut self, _crc: u32, _size: u64) -> Result<Bytes, GatewayError> {
+        let _entry = match self.current.take() {
+            Some(value) => value,
+            None => return Err(GatewayError::Upstream("No archive entry was started".to_string()))
+        };
+        if !_entry.is_zip64 && _size >= ZIP64_LIMIT {
+            log::error!("`{}` changed while it was archived, size {} exceeds the listed size", _entry.name, _size);
+            return Err(GatewayError::Upstream(format!("`{}` changed while it was archived", _entry.name)))
+        }
+        let mut _descriptor = BytesMut::new();
+        _descriptor.put_u32_le(0x08074b50);
+        _descriptor.put_u32_le(_crc);
+        if _entry.is_zip64 {
+            _descriptor.put_u64_le(_size);
+            _descriptor.put_u64_le(_size);
+        } else {
+            _descriptor.put_u32_le(_size as u32);
+            _descriptor.put_u32_le(_size as u32);
+        }
+        //  Central directory record, the values out of range go to the ZIP64 extra field
+        let mut _zip64_extra = BytesMut::new();
+        if _size >= ZIP64_LIMIT {
+            _zip64_extra.put_u64_le(_size);
+            _zip64_extra.put_u64_le(_size);
+        }
+        if _entry.offset >= ZIP64_LIMIT {
+            _zip64_extra.put_u64_le(_entry.offset);
+        }
+        let _is_zip64 = _entry.is_zip64 || !_zip64_extra.is_empty();
+        let _version = if _is_zip64 { ZIP64_VERSION } else { ZIP_VERSION };
+        let _record = &mut self.central_directory;
+        _record.put_u32_le(0x02014b50);
+        //  Made by Unix, to keep the file permissions
+        _record.put_u16_le(0x0300 | _version);
+        _record.put_u16_le(_version);
+        _record.put_u16_le(ZIP_FLAGS);
+        _record.put_u16_le(0);
+        _record.put_u16_le(_entry.time);
+        _record.put_u16_le(_entry.date);
+        _record.put_u32_le(_crc);
+        _record.put_u32_le(_size.min(ZIP64_LIMIT) as u32);
+        _record.put_u32_le(_size.min(ZIP64_LIMIT) as u32);
+        _record.put_u16_le(_entry.name.len() as u16);
+        _record.put_u16_le(if _zip64_extra.is_empty() { 0 } else { _zip64_extra.len() as u16 + 4 });
+        _record.put_u16_le(0);
+        _record.put_u16_le(0);
+        _record.put_u16_le(0);
+        _record.put_u32_le(0o100644 << 16);
+        _record.put_u32_le(_entry.offset.min(ZIP64_LIMIT) as u32);
+        _record.put_slice(_entry.name.as_bytes());
+        if !_zip64_extra.is_empty() {
+            _record.put_u16_le(0x0001);
+            _record.put_u16_le(_zip64_extra.len() as u16);
+            _record.put_slice(&_zip64_extra);
+        }
+        self.entries += 1;
+        self.offset += _size + _descriptor.len() as u64;
+        Ok(_descriptor.freeze())
+    }
+
+    fn finish(&mut self) -> Bytes {
+        let _central_directory_offset = self.offset;
+        let _central_directory_size = self.central_directory.len() as u64;
+        let mut _end = std::mem::take(&mut self.central_directory);
+        if self.entries >= ZIP64_ENTRIES_LIMIT || _central_directory_offset >= ZIP64_LIMIT || _central_directory_size >= ZIP64_LIMIT {
+            //  ZIP64 end of central directory record and locator
+            _end.put_u32_le(0x06064b50);
+            _end.put_u64_le(44);
+            _end.put_u16_le(0x0300 | ZIP64_VERSION);
+            _end.put_u16_le(ZIP64_VERSION);
+            _end.put_u32_le(0);
+            _end.put_u32_le(0);
+            _end.put_u64_le(self.entries);
+            _end.put_u64_le(self.entries);
+            _end.put_u64_le(_central_directory_size);
+            _end.put_u64_le(_central_directory_offset);
+            _end.put_u32_le(0x07064b50);
+            _end.put_u32_le(0);
+            _end.put_u64_le(_central_directory_offset + _central_directory_size);
+            _end.put_u32_le(1);
+        }
+        _end.put_u32_le(0x06054b50);
+        _end.put_u16_le(0);
+        _end.put_u16_le(0);
+        _end.put_u16_le(self.entries.min(ZIP64_ENTRIES_LIMIT) as u16);
+        _end.put_u16_le(self.entries.min(ZIP64_ENTRIES_LIMIT) as u16);
+        _end.put_u32_le(_central_directory_size.min(ZIP64_LIMIT) as u32);
+        _end.put_u32_le(_central_directory_offset.min(ZIP64_LIMIT) as u32);
+        _end.put_u16_le(0);
+        self.offset += _end.len() as u64;
+        _end.freeze()
+    }
+}
+
+/// MS-DOS time and date of the ZIP headers, it starts on 1980
+fn get_dos_date_time(_date_time: Option<NaiveDateTime>) -> (u16, u16) {
+    match _date_time {
+        Some(value) if value.year() >= 1980 => (
+            ((value.hour() << 11) | (value.minute() << 5) | (value.second() / 2)) as u16,
+            (((value.year() as u32 - 1980) << 9) | (value.month() << 5) | value.day()) as u16,
+        ),
+        _ => (0, (1 << 5) | 1),
+    }
+}
+
+type ObjectStream = Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>;
+
+enum ArchiveStep {
+    Next,
+    Data(ObjectStream, crc32fast::Hasher, u64),
+    Done,
+}
+
+struct ArchiveState {
+    client: Arc<Client>,
+    http_client: reqwest::Client,
+    config: Arc<GatewayConfig>,
+    entries: VecDeque<ArchiveEntry>,
+    writer: ZipWriter,
+    step: ArchiveStep,
+}
+
+/// ZIP of the entries built while the objects are downloaded, only a chunk of an object is kept in memory
+pub fn get_archive_stream(_client: Arc<Client>, _http_client: reqwest::Client, _config: Arc<GatewayConfig>, _entries: Vec<ArchiveEntry>) -> impl Stream<Item = Result<Bytes, GatewayError>> + Send + 'static {
+    let _state = ArchiveState {
+        client: _client,
+        http_client: _http_client,
+        config: _config,
+        entries: _entries.into(),
+        writer: ZipWriter::default(),
+        step: ArchiveStep::Next,
+    };
+    futures_util::stream::try_unfold(_state, |mut _state| async move {
+        loop {
+            match &mut _state.step {
+                ArchiveStep::Next => {
+                    let _entry = match _state.entries.pop_front() {
+                        Some(value) => value,
+                        None => {
+                            _state.step = ArchiveStep::Done;
+                            let _end = _state.writer.finish();
+                            return Ok(Some((_end, _state)))
+                        }
+                    };
+                    let _response = match get_object_stream(&_state.client, &_state.http_client, &_state.config, _entry.file_name.to_owned(), None, Vec::new()).await {
+                        Ok(value) => value,
+                        //  Removed after the listing
+                        Err(GatewayError::NotFound(_)) => continue,
+                        Err(error) => {
+                            log::error!("Error archiving `{}`: {}", _entry.file_name, error);
+                            return Err(error)
+                        }
+                    };
+                    let _header = _state.writer.start_entry(&_entry);
+                    _state.step = ArchiveStep::Data(Box::pin(_response.bytes_stream()), crc32fast::Hasher::new(), 0);
+                    return Ok(Some((_header, _state)))
+                },
+                ArchiveStep::Data(_stream, _hasher, _size) => match _stream.next().await {
+                    Some(Ok(_chunk)) => {
+                        _hasher.update(&_chunk);
+                        *_size += _chunk.len() as u64;
+                        return Ok(Some((_chunk, _state)))
+                    },
+                    Some(Err(error)) => {
+                        log::error!("Error archiving: {}", error);
+                        return Err(GatewayError::Upstream(error.to_string()))
+                    },
+                    None => {
+                        let _crc = _hasher.clone().finalize();
+                        let _descriptor = _state.writer.finish_entry(_crc, *_size)?;
+                        _state.step = ArchiveStep::Next;
+                        return Ok(Some((_descriptor, _state)))
+                    }
+                },
+                ArchiveStep::Done => return Ok(None),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+    use super::*;
+
+    fn get_entry(_name: &str, _size: u64) -> ArchiveEntry {
+        ArchiveEntry {
+            file_name: format!("11/client/attachment/c_order/1/{}", _name),
+            name: _name.to_owned(),
+            size: _size,
+            last_modified: NaiveDateTime::parse_from_str("2024-05-17 10:30:20", "%Y-%m-%d %H:%M:%S").ok(),
+        }
+    }
+
+    /// Archive of small entries built in memory
+    fn write_archive(_files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut _writer = ZipWriter::default();
+        let mut _archive = Vec::new();
+        for (_name, _data) in _files {
+            _archive.extend_from_slice(&_writer.start_entry(&get_entry(_name, _data.len() as u64)));
+            _archive.extend_from_slice(_data);
+            _archive.extend_from_slice(&_writer.finish_entry(crc32fast::hash(_data), _data.len() as u64).unwrap());
+        }
+        _archive.extend_from_slice(&_writer.finish());
+        assert_eq!(_writer.offset, _archive.len() as u64);
+        _archive
+    }
+
+    fn read_entry(_archive: &mut zip::ZipArchive<Cursor<Vec<u8>>>, _index: usize) -> (String, Vec<u8>) {
+        let mut _file = _archive.by_index(_index).unwrap();
+        let mut _data = Vec::new();
+        //  The reader validates the CRC at the end of the entry
+        _file.read_to_end(&mut _data).unwrap();
+        (_file.name().to_owned(), _data)
+    }
+
+    #[test]
+    fn entries_are_read_back() {
+        let _archive = write_archive(&[("invoice.pdf", b"%PDF-1.4 content"), ("images/logo.png", b"\x89PNG data"), ("empty.txt", b"")]);
+        let mut _archive = zip::ZipArchive::new(Cursor::new(_archive)).unwrap();
+        assert_eq!(_archive.len(), 3);
+        assert_eq!(read_entry(&mut _archive, 0), ("invoice.pdf".to_owned(), b"%PDF-1.4 content".to_vec()));
+        assert_eq!(read_entry(&mut _archive, 1), ("images/logo.png".to_owned(), b"\x89PNG data".to_vec()));
+        assert_eq!(read_entry(&mut _archive, 2), ("empty.txt".to_owned(), Vec::new()));
+        let _file = _archive.by_index(0).unwrap();
+        assert_eq!(_file.compression(), zip::CompressionMethod::Stored);
+        let _last_modified = _file.last_modified().unwrap();
+        assert_eq!((_last_modified.year(), _last_modified.month(), _last_modified.day()), (2024, 5, 17));
+        assert_eq!((_last_modified.hour(), _last_modified.minute(), _last_modified.second()), (10, 30, 20));
+    }
+
+    #[test]
+    fn unicode_names_are_kept() {
+        let _name = "facturación/año ñandú 📄.txt";
+        let _archive = write_archive(&[(_name, "contenido".as_bytes())]);
+        let mut _archive = zip::ZipArchive::new(Cursor::new(_archive)).unwrap();
+        assert_eq!(read_entry(&mut _archive, 0), (_name.to_owned(), "contenido".as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn more_than_65535_entries_use_zip64() {
+        let _names = (0..70_000).map(|index| format!("{}.txt", index)).collect::<Vec<String>>();
+        let _files = _names.iter().map(|_name| (_name.as_str(), _name.as_bytes())).collect::<Vec<(&str, &[u8])>>();
+        let _archive = write_archive(&_files);
+        let mut _archive = zip::ZipArchive::new(Cursor::new(_archive)).unwrap();
+        assert_eq!(_archive.len(), 70_000);
+        assert_eq!(read_entry(&mut _archive, 0), ("0.txt".to_owned(), b"0.txt".to_vec()));
+        assert_eq!(read_entry(&mut _archive, 69_999), ("69999.txt".to_owned(), b"69999.txt".to_vec()));
+    }
+
+    #[test]
+    fn large_sizes_and_offsets_use_zip64() {
+        //  The zeros of the large entry are a hole of a sparse file, only the headers are written
+        const CHUNK_SIZE: u64 = 1024 * 1024;
+        let _large_size = 4096 * CHUNK_SIZE;
+        assert!(_large_size >= ZIP64_LIMIT);
+        let mut _chunk_hasher = crc32fast::Hasher::new();
+        _chunk_hasher.update(&vec![0; CHUNK_SIZE as usize]);
+        let mut _hasher = crc32fast::Hasher::new();
+        for _ in 0..4096 {
+            _hasher.combine(&_chunk_hasher);
+        }
+        let _large_crc = _hasher.finalize();
+        let _path = std::env::temp_dir().join(format!("s3_gateway_zip64_{}.zip", std::process::id()));
+        let mut _file = std::fs::File::create(&_path).unwrap();
+        let mut _writer = ZipWriter::default();
+        _file.write_all(&_writer.start_entry(&get_entry("large.bin", _large_size))).unwrap();
+        _file.seek(SeekFrom::Current(_large_size as i64)).unwrap();
+        _file.write_all(&_writer.finish_entry(_large_crc, _large_size).unwrap()).unwrap();
+        //  Starts after 4 GiB, its offset is in the ZIP64 extra field
+        _file.write_all(&_writer.start_entry(&get_entry("after.txt", 5))).unwrap();
+        _file.write_all(b"after").unwrap();
+        _file.write_all(&_writer.finish_entry(crc32fast::hash(b"after"), 5).unwrap()).unwrap();
+        _file.write_all(&_writer.finish()).unwrap();
+        drop(_file);
+        let _result = std::panic::catch_unwind(|| {
+            let mut _archive = zip::ZipArchive::new(std::fs::File::open(&_path).unwrap()).unwrap();
+            assert_eq!(_archive.len(), 2);
+            let _large = _archive.by_index(0).unwrap();
+            assert_eq!(_large.name(), "large.bin");
+            assert_eq!(_large.size(), _large_size);
+            assert_eq!(_large.compressed_size(), _large_size);
+            assert_eq!(_large.crc32(), _large_crc);
+            drop(_large);
+            let mut _after = _archive.by_index(1).unwrap();
+            assert_eq!(_after.name(), "after.txt");
+            let mut _data = String::new();
+            _after.read_to_string(&mut _data).unwrap();
+            assert_eq!(_data, "after");
+        });
+        let _ = std::fs::remove_file(&_path);
+        assert!(_result.is_ok());
+    }
+
+    #[test]
+    fn entry_larger_than_listed_is_rejected() {
+        let mut _writer = ZipWriter::default();
+        _writer.start_entry(&get_entry("grown.bin", 10));
+        assert!(_writer.finish_entry(0, ZIP64_LIMIT).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use chrono::{DateTime, Utc};
+use salvo::http::header;
+use salvo::Request;
+
+/// Result of evaluate the conditional headers against the current object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    /// Serve the object
+    Proceed,
+    /// The client copy is still valid, answer with `304 Not Modified`
+    NotModified,
+    /// The object changed, answer with `412 Precondition Failed`
+    Failed,
+}
+
+/// Range and conditional headers sent by the client on downloads
+#[derive(Debug, Clone, Default)]
+pub struct ConditionalHeaders {
+    pub range: Option<String>,
+    pub if_range: Option<String>,
+    pub if_match: Option<String>,
+    pub if_none_match: Option<String>,
+    pub if_modified_since: Option<String>,
+    pub if_unmodified_since: Option<String>,
+}
+
+impl ConditionalHeaders {
+    pub fn from_request(_req: &Request) -> Self {
+        ConditionalHeaders {
+            range: _req.header::<String>(header::RANGE),
+            if_range: _req.header::<String>(header::IF_RANGE),
+            if_match: _req.header::<String>(header::IF_MATCH),
+            if_none_match: _req.header::<String>(header::IF_NONE_MATCH),
+            if_modified_since: _req.header::<String>(header::IF_MODIFIED_SINCE),
+            if_unmodified_since: _req.header::<String>(header::IF_UNMODIFIED_SINCE),
+        }
+    }
+
+    pub fn has_preconditions(&self) -> bool {
+        self.if_match.is_some() || self.if_none_match.is_some() || self.if_modified_since.is_some() || self.if_unmodified_since.is_some()
+    }
+
+    /// Headers to forward to S3, it evaluates them with the same rules
+    pub fn to_forward_headers(&self) -> Vec<(String, String)> {
+        let mut _headers = Vec::new();
+        let _values = [
+            (header::RANGE, &self.range),
+            (header::IF_RANGE, &self.if_range),
+            (header::IF_MATCH, &self.if_match),
+            (header::IF_NONE_MATCH, &self.if_none_match),
+            (header::IF_MODIFIED_SINCE, &self.if_modified_since),
+            (header::IF_UNMODIFIED_SINCE, &self.if_unmodified_since),
+        ];
+        for (_name, _value) in _values {
+            if let Some(_value) = _value {
+                _headers.push((_name.to_string(), _value.to_owned()));
+            }
+        }
+        _headers
+    }
+
+    /// Evaluate the preconditions in the order defined by RFC 9110 section 13.2.2
+    pub fn evaluate(&self, _etag: Option<&str>, _last_modified: Option<DateTime<Utc>>) -> Precondition {
+        if let Some(_if_match) = &self.if_match {
+            if !is_etag_matched(_if_match, _etag, true) {
+                return Precondition::Failed
+            }
+        } else if let Some(_if_unmodified_since) = &self.if_unmodified_since {
+            if let (Some(_date), Some(_last_modified)) = (parse_http_date(_if_unmodified_since), _last_modified) {
+                if _last_modified.timestamp() > _date.timestamp() {
+                    return Precondition::Failed
+                }
+            }
+        }
+        if let Some(_if_none_match) = &self.if_none_match {
+            if is_etag_matched(_if_none_match, _etag, false) {
+                return Precondition::NotModified
+            }
+        } else if let Some(_if_modified_since) = &self.if_modified_since {
+            if let (Some(_date), Some(_last_modified)) = (parse_http_date(_if_modified_since), _last_modified) {
+                if _last_modified.timestamp() <= _date.timestamp() {
+                    return Precondition::NotModified
+                }
+            }
+        }
+        Precondition::Proceed
+    }
+}
+
+fn normalize_etag(_value: &str) -> &str {
+    _value.trim().trim_start_matches("W/").trim_matches('"')
+}
+
+/// `If-Match` uses strong comparison, `If-None-Match` the weak one
+fn is_etag_matched(_header_value: &str, _etag: Option<&str>, _strong: bool) -> bool {
+    let _etag = match _etag {
+        Some(value) => value,
+        None => return false,
+    };
+    _header_value.split(',').any(|_candidate| {
+        let _candidate = _candidate.trim();
+        if _candidate.eq("*") {
+            return true
+        }
+        if _strong && _candidate.starts_with("W/") {
+            return false
+        }
+        normalize_etag(_candidate).eq(normalize_etag(_etag))
+    })
+}
+
+pub fn parse_http_date(_value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(_value.trim())
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+pub fn format_http_date(_value: DateTime<Utc>) -> String {
+    _value.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETAG: &str = "\"abc\"";
+    const LAST_MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+    const BEFORE: &str = "Tue, 20 Oct 2015 07:28:00 GMT";
+    const AFTER: &str = "Thu, 22 Oct 2015 07:28:00 GMT";
+
+    fn evaluate(_headers: ConditionalHeaders) -> Precondition {
+        _headers.evaluate(Some(ETAG), parse_http_date(LAST_MODIFIED))
+    }
+
+    #[test]
+    fn proceeds_without_preconditions() {
+        let _headers = ConditionalHeaders::default();
+        assert!(!_headers.has_preconditions());
+        assert_eq!(evaluate(_headers), Precondition::Proceed);
+    }
+
+    #[test]
+    fn if_match_takes_precedence_over_if_unmodified_since() {
+        //  A matching ETag ignores the date, even if the object was modified after it
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_match: Some(ETAG.to_owned()),
+            if_unmodified_since: Some(BEFORE.to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::Proceed);
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_match: Some("\"other\"".to_owned()),
+            if_unmodified_since: Some(AFTER.to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::Failed);
+    }
+
+    #[test]
+    fn if_unmodified_since_without_if_match() {
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_unmodified_since: Some(BEFORE.to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::Failed);
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_unmodified_since: Some(LAST_MODIFIED.to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::Proceed);
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        //  A different ETag serves the object, even if the date says it was not modified
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_none_match: Some("\"other\"".to_owned()),
+            if_modified_since: Some(AFTER.to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::Proceed);
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_none_match: Some(ETAG.to_owned()),
+            if_modified_since: Some(BEFORE.to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::NotModified);
+    }
+
+    #[test]
+    fn if_modified_since_without_if_none_match() {
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_modified_since: Some(LAST_MODIFIED.to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::NotModified);
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_modified_since: Some(BEFORE.to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::Proceed);
+    }
+
+    #[test]
+    fn wildcard_matches_existing_objects() {
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_match: Some("*".to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::Proceed);
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_none_match: Some("*".to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::NotModified);
+        let _headers = ConditionalHeaders {
+            if_match: Some("*".to_owned()),
+            ..ConditionalHeaders::default()
+        };
+        assert_eq!(_headers.evaluate(None, None), Precondition::Failed);
+    }
+
+    #[test]
+    fn weak_etags_only_match_if_none_match() {
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_match: Some("W/\"abc\"".to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::Failed);
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_none_match: Some("W/\"abc\"".to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::NotModified);
+    }
+
+    #[test]
+    fn etag_lists_match_any_value() {
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_match: Some("\"x\", \"abc\", \"y\"".to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::Proceed);
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_none_match: Some("\"x\", W/\"abc\"".to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::NotModified);
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_none_match: Some("\"x\", \"y\"".to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::Proceed);
+    }
+
+    #[test]
+    fn failed_precondition_wins_over_not_modified() {
+        //  412 is evaluated before 304
+        assert_eq!(evaluate(ConditionalHeaders {
+            if_match: Some("\"other\"".to_owned()),
+            if_none_match: Some(ETAG.to_owned()),
+            ..ConditionalHeaders::default()
+        }), Precondition::Failed);
+    }
+}
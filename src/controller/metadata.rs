@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+
+use minio::s3::args::SetObjectTagsArgs;
+use minio::s3::client::Client;
+use minio::s3::response::StatObjectResponse;
+use minio::s3::utils::{urlencode, Multimap};
+use regex::Regex;
+use salvo::Request;
+use serde::Deserialize;
+use xmltree::{Element, EmitterConfig, XMLNode};
+
+use crate::config::GatewayConfig;
+use crate::controller::s3::{copy_object, get_object_stat, Resource};
+use crate::error::GatewayError;
+
+/// Prefix of the user metadata in S3 headers and presigned URLs
+pub const USER_METADATA_PREFIX: &str = "x-amz-meta-";
+pub const ORIGINAL_FILE_NAME_KEY: &str = "original-file-name";
+pub const UPLOADED_BY_KEY: &str = "uploaded-by";
+pub const CONTAINER_TYPE_KEY: &str = "container-type";
+pub const TABLE_NAME_KEY: &str = "table-name";
+pub const COLUMN_NAME_KEY: &str = "column-name";
+pub const RECORD_ID_KEY: &str = "record-id";
+pub const DESCRIPTION_KEY: &str = "description";
+pub const DELETED_AT_KEY: &str = "deleted-at";
+pub const DELETED_BY_KEY: &str = "deleted-by";
+pub const TRASH_ORIGIN_KEY: &str = "trash-origin";
+pub const SCAN_STATUS_KEY: &str = "scan-status";
+pub const SCAN_SIGNATURE_KEY: &str = "scan-signature";
+pub const SCANNED_AT_KEY: &str = "scanned-at";
+pub const QUARANTINE_ORIGIN_KEY: &str = "quarantine-origin";
+/// Metadata written only by the gateway, the scan status or the trash data can not be sent by the callers
+pub const RESERVED_METADATA_KEYS: [&str; 8] = [UPLOADED_BY_KEY, DELETED_AT_KEY, DELETED_BY_KEY, TRASH_ORIGIN_KEY, SCAN_STATUS_KEY, SCAN_SIGNATURE_KEY, SCANNED_AT_KEY, QUARANTINE_ORIGIN_KEY];
+
+/// S3 limits for user metadata and tags
+const MAX_USER_METADATA_SIZE: usize = 2048;
+const MAX_TAGS: usize = 10;
+const MAX_TAG_KEY_LENGTH: usize = 128;
+const MAX_TAG_VALUE_LENGTH: usize = 256;
+
+/// Headers kept when the user metadata is replaced, S3 drops them otherwise
+const PRESERVED_HEADERS: [&str; 5] = ["Content-Type", "Content-Disposition", "Content-Encoding", "Content-Language", "Cache-Control"];
+
+pub fn is_reserved_metadata_key(_key: &str) -> bool {
+    let _key = _key.trim();
+    RESERVED_METADATA_KEYS.iter().any(|_reserved_key| _reserved_key.eq_ignore_ascii_case(_key))
+}
+
+/// User metadata and tags attached to an object
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ObjectMetadata {
+    pub user_metadata: Option<HashMap<String, String>>,
+    pub tags: Option<HashMap<String, String>>,
+}
+
+impl ObjectMetadata {
+    /// Metadata sent as `description`, `meta.<key>` and `tag.<key>` query parameters, the reserved keys are discarded
+    pub fn from_request(_req: &Request) -> Self {
+        let mut _metadata = ObjectMetadata::default();
+        for (_key, _value) in _req.queries().iter() {
+            if let Some(_name) = _key.strip_prefix("meta.") {
+                if is_reserved_metadata_key(_name) {
+                    log::warn!("Reserved Metadata Key {:?} discarded", _name);
+                    continue
+                }
+                _metadata.insert_user_metadata(_name, Some(_value.to_owned()));
+            } else if let Some(_name) = _key.strip_prefix("tag.") {
+                _metadata.tags.get_or_insert_with(HashMap::new).insert(_name.to_owned(), _value.to_owned());
+            }
+        }
+        _metadata.insert_user_metadata(DESCRIPTION_KEY, _req.query::<String>("description"));
+        _metadata
+    }
+
+    /// Add a user metadata entry, the keys are case insensitive for S3
+    pub fn insert_user_metadata(&mut self, _key: &str, _value: Option<String>) {
+        if let Some(_value) = _value {
+            self.user_metadata.get_or_insert_with(HashMap::new).insert(_key.to_lowercase(), _value);
+        }
+    }
+
+    /// The metadata sent to update an object can not set or remove the reserved keys
+    pub fn validate_reserved_keys(&self) -> Result<(), GatewayError> {
+        let _reserved_key = self.user_metadata.iter()
+            .flat_map(|_user_metadata| _user_metadata.keys())
+            .find(|_key| is_reserved_metadata_key(_key));
+        match _reserved_key {
+            Some(_key) => {
+                log::error!("Reserved Metadata Key {:?}", _key);
+                Err(GatewayError::Validation(format!("Metadata Key `{}` is reserved", _key)))
+            },
+            None => Ok(()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), GatewayError> {
+        let _key_pattern = Regex::new(r"^[a-z0-9][a-z0-9_-]*$").unwrap();
+        let _tag_pattern = Regex::new(r"^[\p{L}\p{N}\s+\-=._:/@]*$").unwrap();
+        if let Some(_user_metadata) = &self.user_metadata {
+            let mut _size = 0;
+            for (_key, _value) in _user_metadata {
+                if !_key_pattern.is_match(_key) {
+                    log::error!("Invalid Metadata Key {:?}", _key);
+                    return Err(GatewayError::Validation(format!("Invalid Metadata Key `{}`, only letters, numbers, `-` and `_` are allowed", _key)))
+                }
+                _size += _key.len() + encode_metadata_value(_value).len();
+            }
+            if _size > MAX_USER_METADATA_SIZE {
+                log::error!("Metadata size {} exceeds {} bytes", _size, MAX_USER_METADATA_SIZE);
+                return Err(GatewayError::Validation(format!("Metadata can not exceed {} bytes", MAX_USER_METADATA_SIZE)))
+            }
+        }
+        if let Some(_tags) = &self.tags {
+            if _tags.len() > MAX_TAGS {
+                log::error!("Too many Tags {}", _tags.len());
+                return Err(GatewayError::Validation(format!("Only {} Tags are allowed by object", MAX_TAGS)))
+            }
+            for (_key, _value) in _tags {
+                if _key.is_empty() || _key.chars().count() > MAX_TAG_KEY_LENGTH || _value.chars().count() > MAX_TAG_VALUE_LENGTH || !_tag_pattern.is_match(_key) || !_tag_pattern.is_match(_value) {
+                    log::error!("Invalid Tag {:?}={:?}", _key, _value);
+                    return Err(GatewayError::Validation(format!("Invalid Tag `{}`", _key)))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// User metadata and tags as S3 headers, also used as query of presigned URLs because only the host is signed
+    pub fn to_headers(&self) -> Multimap {
+        let mut _headers = Multimap::new();
+        if let Some(_user_metadata) = &self.user_metadata {
+            for (_key, _value) in _user_metadata {
+                _headers.insert(format!("{}{}", USER_METADATA_PREFIX, _key), encode_metadata_value(_value));
+            }
+        }
+        if let Some(_tagging) = self.get_tagging() {
+            _headers.insert("x-amz-tagging".to_string(), _tagging);
+        }
+        _headers
+    }
+
+    /// User metadata and tags as fields of a presigned POST form, the form receives the tags as XML
+    pub fn to_form_fields(&self) -> HashMap<String, String> {
+        let mut _fields: HashMap<String, String> = HashMap::new();
+        if let Some(_user_metadata) = &self.user_metadata {
+            for (_key, _value) in _user_metadata {
+                _fields.insert(format!("{}{}", USER_METADATA_PREFIX, _key), encode_metadata_value(_value));
+            }
+        }
+        if let Some(_tags) = self.tags.as_ref().filter(|_tags| !_tags.is_empty()) {
+            let mut _tag_set = Element::new("TagSet");
+            for (_key, _value) in _tags {
+                let mut _tag = Element::new("Tag");
+                for (_name, _text) in [("Key", _key), ("Value", _value)] {
+                    let mut _element = Element::new(_name);
+                    _element.children.push(XMLNode::Text(_text.to_owned()));
+                    _tag.children.push(XMLNode::Element(_element));
+                }
+                _tag_set.children.push(XMLNode::Element(_tag));
+            }
+            let mut _tagging = Element::new("Tagging");
+            _tagging.children.push(XMLNode::Element(_tag_set));
+            let mut _xml: Vec<u8> = Vec::new();
+            let _config = EmitterConfig::new().write_document_declaration(false);
+            if _tagging.write_with_config(&mut _xml, _config).is_ok() {
+                _fields.insert("tagging".to_string(), String::from_utf8_lossy(&_xml).into_owned());
+            }
+        }
+        _fields
+    }
+
+    fn get_tagging(&self) -> Option<String> {
+        let _tags = self.tags.as_ref()?;
+        if _tags.is_empty() {
+            return None
+        }
+        Some(_tags.iter()
+            .map(|(_key, _value)| format!("{}={}", urlencode(_key), urlencode(_value)))
+            .collect::<Vec<String>>()
+            .join("&"))
+    }
+}
+
+/// S3 only supports ASCII on metadata, other values are stored URL encoded
+pub fn encode_metadata_value(_value: &str) -> String {
+    match _value.chars().all(|_char| _char.is_ascii() && !_char.is_ascii_control()) {
+        true => _value.to_owned(),
+        false => urlencode(_value).into_owned(),
+    }
+}
+
+pub fn decode_metadata_value(_value: &str) -> String {
+    match urlencoding::decode(_value) {
+        Ok(value) => value.into_owned(),
+        Err(_) => _value.to_owned(),
+    }
+}
+
+/// Decoded value of a user metadata entry, listings return the keys with the `X-Amz-Meta-` prefix
+pub fn get_user_metadata_value(_user_metadata: Option<&HashMap<String, String>>, _name: &str) -> Option<String> {
+    _user_metadata.and_then(|_user_metadata| {
+        _user_metadata.iter()
+            .find(|(_key, _)| {
+                let _key = _key.to_lowercase();
+                _key.eq(_name) || _key.strip_prefix(USER_METADATA_PREFIX).is_some_and(|_key| _key.eq(_name))
+            })
+            .map(|(_, _value)| decode_metadata_value(_value))
+    })
+}
+
+/// Name sent on upload
+pub fn get_display_name(_file_name: &str, _user_metadata: Option<&HashMap<String, String>>) -> String {
+    match get_user_metadata_value(_user_metadata, ORIGINAL_FILE_NAME_KEY) {
+        Some(value) if !value.trim().is_empty() => value,
+        //  Objects uploaded before keep the name of the key
+        _ => _file_name.rsplit('/').next().unwrap_or(_file_name).to_owned(),
+    }
+}
+
+/// `Content-Disposition` with an ASCII fallback for old clients and the UTF-8 name of RFC 6266
+pub fn get_content_disposition(_is_attachment: bool, _display_name: &str) -> String {
+    let _disposition = match _is_attachment {
+        true => "attachment",
+        false => "inline",
+    };
+    let _fallback_name = _display_name.chars()
+        .map(|_char| match _char.is_ascii() && !_char.is_ascii_control() && _char != '"' && _char != '\\' {
+            true => _char,
+            false => '_',
+        })
+        .collect::<String>();
+    format!("{}; filename=\"{}\"; filename*=UTF-8''{}", _disposition, _fallback_name, urlencode(_display_name))
+}
+
+/// Headers to copy an object replacing its user metadata, the content headers of `_stat` are kept
+pub fn get_replace_headers(_stat: &StatObjectResponse, _user_metadata: HashMap<String, String>) -> Multimap {
+    let mut _headers = ObjectMetadata {
+        user_metadata: Some(_user_metadata),
+        tags: None,
+    }.to_headers();
+    for _name in PRESERVED_HEADERS {
+        if let Some(_value) = _stat.headers.get(_name).and_then(|value| value.to_str().ok()) {
+            _headers.insert(_name.to_string(), _value.to_owned());
+        }
+    }
+    _headers
+}
+
+/// Replace the tags and merge the user metadata of an existing object, an empty value removes the metadata entry
+pub async fn update_object_metadata(_client: &Client, _config: &GatewayConfig, _file_name: String, _metadata: ObjectMetadata) -> Result<Resource, GatewayError> {
+    _metadata.validate_reserved_keys()?;
+    _metadata.validate()?;
+    if let Some(_tags) = &_metadata.tags {
+        let _args = SetObjectTagsArgs::new(&_config.bucket_name, &_file_name, _tags)?;
+        _client.set_object_tags(&_args).await?;
+    }
+    if let Some(_user_metadata) = &_metadata.user_metadata {
+        let _stat = get_object_stat(_client, _config, _file_name.to_owned(), None).await?;
+        let mut _merged_metadata = _stat.user_metadata.to_owned();
+        for (_key, _value) in _user_metadata {
+            match _value.is_empty() {
+                true => _merged_metadata.remove(_key),
+                false => _merged_metadata.insert(_key.to_owned(), _value.to_owned()),
+            };
+        }
+        let _headers = get_replace_headers(&_stat, _merged_metadata);
+        //  S3 only allows to copy an object to itself when the metadata is replaced
+        copy_object(_client, _config, &_stat, &_file_name, Some(_headers)).await?;
+    }
+    let _stat = get_object_stat(_client, _config, _file_name, None).await?;
+    Ok(Resource::from_stat(&_stat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_request(_query: &str) -> Request {
+        let mut _req = Request::default();
+        *_req.uri_mut() = format!("http://localhost/api/resources/file.txt?{}", _query).parse().unwrap();
+        _req
+    }
+
+    #[test]
+    fn reserved_keys_are_discarded_from_the_query() {
+        let _req = get_request("meta.scan-status=clean&meta.Uploaded-By=admin&meta.deleted-at=now&meta.owner=sales&tag.status=draft");
+        let _metadata = ObjectMetadata::from_request(&_req);
+        let _user_metadata = _metadata.user_metadata.unwrap();
+        assert_eq!(_user_metadata.len(), 1);
+        assert_eq!(_user_metadata.get("owner"), Some(&"sales".to_owned()));
+        assert_eq!(_metadata.tags.unwrap().get("status"), Some(&"draft".to_owned()));
+    }
+
+    #[test]
+    fn reserved_keys_can_not_be_updated() {
+        for _key in RESERVED_METADATA_KEYS {
+            for _value in ["clean", ""] {
+                let _metadata = ObjectMetadata {
+                    user_metadata: Some(HashMap::from([(_key.to_owned(), _value.to_owned())])),
+                    tags: None,
+                };
+                assert!(matches!(_metadata.validate_reserved_keys(), Err(GatewayError::Validation(_))));
+            }
+        }
+        let _metadata = ObjectMetadata {
+            user_metadata: Some(HashMap::from([("owner".to_owned(), "sales".to_owned())])),
+            tags: None,
+        };
+        assert!(_metadata.validate_reserved_keys().is_ok());
+    }
+
+    #[test]
+    fn gateway_can_insert_reserved_keys() {
+        let mut _metadata = ObjectMetadata::from_request(&get_request("meta.scan-status=clean"));
+        _metadata.insert_user_metadata(SCAN_STATUS_KEY, Some("pending".to_owned()));
+        assert_eq!(_metadata.user_metadata.unwrap().get(SCAN_STATUS_KEY), Some(&"pending".to_owned()));
+    }
+
+    #[test]
+    fn content_disposition_of_ascii_names() {
+        assert_eq!(get_content_disposition(false, "invoice.pdf"), "inline; filename=\"invoice.pdf\"; filename*=UTF-8''invoice.pdf");
+        assert_eq!(get_content_disposition(true, "invoice.pdf"), "attachment; filename=\"invoice.pdf\"; filename*=UTF-8''invoice.pdf");
+    }
+
+    #[test]
+    fn content_disposition_of_non_ascii_names() {
+        assert_eq!(get_content_disposition(true, "Factura Nº 12.pdf"), "attachment; filename=\"Factura N_ 12.pdf\"; filename*=UTF-8''Factura%20N%C2%BA%2012.pdf");
+        assert_eq!(get_content_disposition(true, "日本.txt"), "attachment; filename=\"__.txt\"; filename*=UTF-8''%E6%97%A5%E6%9C%AC.txt");
+    }
+
+    #[test]
+    fn content_disposition_escapes_the_fallback() {
+        //  Quotes, backslashes and control characters can not break the quoted fallback
+        assert_eq!(get_content_disposition(false, "a\"b\\c\nd.txt"), "inline; filename=\"a_b_c_d.txt\"; filename*=UTF-8''a%22b%5Cc%0Ad.txt");
+    }
+
+    #[test]
+    fn display_name_falls_back_to_the_key() {
+        let _file_name = "11/client/attachment/c_order/1/factura_n__12.pdf";
+        let _user_metadata = HashMap::from([("X-Amz-Meta-Original-File-Name".to_owned(), "Factura%20N%C2%BA%2012.pdf".to_owned())]);
+        assert_eq!(get_display_name(_file_name, Some(&_user_metadata)), "Factura Nº 12.pdf");
+        let _user_metadata = HashMap::from([(ORIGINAL_FILE_NAME_KEY.to_owned(), " ".to_owned())]);
+        assert_eq!(get_display_name(_file_name, Some(&_user_metadata)), "factura_n__12.pdf");
+        assert_eq!(get_display_name(_file_name, None), "factura_n__12.pdf");
+        assert_eq!(get_display_name("file.pdf", None), "file.pdf");
+    }
+}
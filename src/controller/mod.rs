@@ -1 +1,11 @@
-pub mod s3;
\ No newline at end of file
+pub mod antivirus;
+pub mod archive;
+pub mod conditional;
+pub mod metadata;
+pub mod multipart;
+pub mod s3;
+pub mod sniffing;
+pub mod thumbnail;
+pub mod transfer;
+pub mod trash;
+pub mod versioning;
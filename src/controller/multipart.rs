@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+
+use bytes::Buf;
+use http::Method;
+use minio::s3::args::{AbortMultipartUploadArgs, CompleteMultipartUploadArgs, CreateMultipartUploadArgs, GetPresignedObjectUrlArgs, StatObjectArgs};
+use minio::s3::client::Client;
+use minio::s3::types::Part;
+use minio::s3::utils::{get_default_text, get_option_text, Multimap};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use xmltree::Element;
+
+use crate::config::GatewayConfig;
+use crate::controller::antivirus::put_pending_scan_marker;
+use crate::controller::metadata::ObjectMetadata;
+use crate::controller::s3::{get_object_upload_policy, get_valid_path, UploadedObject};
+use crate::error::GatewayError;
+
+/// S3 allows part numbers from 1 to 10000
+const MAX_PART_NUMBER: u16 = 10000;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MultipartUpload {
+    pub file_name: String,
+    pub upload_id: String,
+    pub initiated: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MultipartUploadResponse {
+    pub parent_folder: Option<String>,
+    pub uploads: Vec<MultipartUpload>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PresignedPart {
+    pub url: String,
+    pub file_name: String,
+    pub upload_id: String,
+    pub part_number: u16,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct UploadedPart {
+    pub part_number: u16,
+    pub etag: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CompleteMultipartUploadRequest {
+    pub parts: Vec<UploadedPart>,
+}
+
+/// Start a multipart upload, the declared total size is mandatory when the upload policy limits the size
+pub async fn create_multipart_upload(_client: &Client, _config: &GatewayConfig, _file_name: String, _size: Option<usize>, _metadata: &ObjectMetadata) -> Result<MultipartUpload, GatewayError> {
+    _metadata.validate()?;
+    let mut _headers = _metadata.to_headers();
+    let _content_type = mime_guess::from_path(Path::new(&_file_name)).first_or_octet_stream();
+    //  The parts are checked again on complete, before S3 assemble the object
+    if let Some(_policy) = get_object_upload_policy(_config, &_file_name) {
+        if _policy.max_size.is_some() && _size.is_none() {
+            log::error!("Size is Mandatory for `{}`", _file_name);
+            return Err(GatewayError::Validation("Size is Mandatory, the upload policy limits the size of the files".to_string()))
+        }
+        _policy.validate(Some(_content_type.as_ref()), _size)?;
+    }
+    _headers.insert("Content-Type".to_string(), _content_type.to_string());
+    let mut _args = CreateMultipartUploadArgs::new(&_config.bucket_name, &_file_name)?;
+    _args.headers = Some(&_headers);
+    match _client.create_multipart_upload(&_args).await {
+        Ok(value) => Ok(MultipartUpload {
+            file_name: _file_name.to_owned(),
+            upload_id: value.upload_id,
+            initiated: None,
+        }),
+        Err(error) => Err(error.into())
+    }
+}
+
+pub async fn request_signed_part_url(_client: &Client, _config: &GatewayConfig, _file_name: String, _upload_id: String, _part_number: u16, _seconds: Option<u32>) -> Result<PresignedPart, GatewayError> {
+    if _part_number == 0 || _part_number > MAX_PART_NUMBER {
+        log::error!("Invalid Part Number {}", _part_number);
+        return Err(GatewayError::Validation(format!("Part Number must be between 1 and {}", MAX_PART_NUMBER)))
+    }
+    let mut _query_params = Multimap::new();
+    _query_params.insert("partNumber".to_string(), _part_number.to_string());
+    _query_params.insert("uploadId".to_string(), _upload_id.to_owned());
+    let mut _args = GetPresignedObjectUrlArgs::new(&_config.bucket_name, &_file_name, Method::PUT)?;
+    _args.extra_query_params = Some(&_query_params);
+    if let Some(seconds) = _seconds {
+        _args.expiry_seconds = Some(seconds);
+    }
+    match _client.get_presigned_object_url(&_args).await {
+        Ok(value) => Ok(PresignedPart {
+            url: value.url,
+            file_name: _file_name.to_owned(),
+            upload_id: _upload_id.to_owned(),
+            part_number: _part_number,
+        }),
+        Err(error) => Err(error.into())
+    }
+}
+
+/// ETag and size of each part uploaded to S3 by part number, minio client does not expose `ListParts`
+async fn list_uploaded_parts(_client: &Client, _config: &GatewayConfig, _file_name: &str, _upload_id: &str) -> Result<HashMap<u16, (String, usize)>, GatewayError> {
+    let _region = _client.get_region(&_config.bucket_name, None).await?;
+    let mut _parts = HashMap::new();
+    let mut _part_number_marker: Option<String> = None;
+    loop {
+        let mut _headers = Multimap::new();
+        let mut _query_params = Multimap::new();
+        _query_params.insert("uploadId".to_string(), _upload_id.to_owned());
+        if let Some(_marker) = &_part_number_marker {
+            _query_params.insert("part-number-marker".to_string(), _marker.to_owned());
+        }
+        let _response = _client.execute(Method::GET, &_region, &mut _headers, &_query_params, Some(&_config.bucket_name), Some(_file_name), None).await?;
+        let _body = match _response.bytes().await {
+            Ok(value) => value,
+            Err(error) => return Err(GatewayError::Upstream(error.to_string()))
+        };
+        let mut _root = match Element::parse(_body.reader()) {
+            Ok(value) => value,
+            Err(error) => return Err(GatewayError::Upstream(error.to_string()))
+        };
+        while let Some(_part) = _root.take_child("Part") {
+            let _number = get_default_text(&_part, "PartNumber").parse::<u16>().unwrap_or_default();
+            let _etag = get_default_text(&_part, "ETag").trim_matches('"').to_owned();
+            let _size = get_default_text(&_part, "Size").parse::<usize>().unwrap_or_default();
+            _parts.insert(_number, (_etag, _size));
+        }
+        if !get_default_text(&_root, "IsTruncated").eq("true") {
+            break;
+        }
+        _part_number_marker = get_option_text(&_root, "NextPartNumberMarker");
+    }
+    Ok(_parts)
+}
+
+/// Size of the object that S3 would assemble with the parts to complete, each part must be the uploaded one
+/// so a part sent again after the check does not match the ETag of the complete
+fn get_parts_size(_uploaded_parts: &HashMap<u16, (String, usize)>, _parts: &[Part]) -> Result<usize, GatewayError> {
+    let mut _size = 0;
+    for _part in _parts {
+        match _uploaded_parts.get(&_part.number) {
+            Some((_etag, _part_size)) if _etag.eq(&_part.etag) => _size += _part_size,
+            _ => {
+                log::error!("Part {} with ETag {:?} was not uploaded", _part.number, _part.etag);
+                return Err(GatewayError::Validation(format!("Part {} was not uploaded or its ETag does not match", _part.number)))
+            }
+        }
+    }
+    Ok(_size)
+}
+
+pub async fn complete_multipart_upload(_client: &Client, _config: &GatewayConfig, _file_name: String, _upload_id: String, _parts: Vec<UploadedPart>) -> Result<UploadedObject, GatewayError> {
+    if _parts.is_empty() {
+        log::error!("Parts are Mandatory");
+        return Err(GatewayError::Validation("Parts are Mandatory".to_string()))
+    }
+    let mut _parts_to_complete = _parts.iter().map(|_part| Part {
+        number: _part.part_number,
+        etag: _part.etag.trim_matches('"').to_owned(),
+    }).collect::<Vec<Part>>();
+    _parts_to_complete.sort_by_key(|_part| _part.number);
+    //  The object must not exist if it is larger, a delete after complete keeps the version in versioned buckets
+    if let Some(_max_size) = get_object_upload_policy(_config, &_file_name).and_then(|_policy| _policy.max_size) {
+        let _uploaded_parts = list_uploaded_parts(_client, _config, &_file_name, &_upload_id).await?;
+        let _size = get_parts_size(&_uploaded_parts, &_parts_to_complete)?;
+        if _size > _max_size {
+            log::error!("Multipart upload `{}` size {} exceeds {}", _file_name, _size, _max_size);
+            if let Err(error) = abort_multipart_upload(_client, _config, _file_name.to_owned(), _upload_id.to_owned()).await {
+                log::warn!("Error aborting the multipart upload `{}`: {}", _file_name, error);
+            }
+            return Err(GatewayError::PayloadTooLarge(format!("Files larger than {} bytes are not allowed", _max_size)))
+        }
+    }
+    let _args = CompleteMultipartUploadArgs::new(&_config.bucket_name, &_file_name, &_upload_id, &_parts_to_complete)?;
+    let _completed = _client.complete_multipart_upload(&_args).await?;
+    let _stat = _client.stat_object(&StatObjectArgs::new(&_config.bucket_name, &_file_name)?).await?;
+    put_pending_scan_marker(_client, _config, &_file_name, Some(&_stat.user_metadata)).await?;
+    Ok(UploadedObject {
+        file_name: _file_name.to_owned(),
+        size: _stat.size,
+        etag: Some(_completed.etag),
+        version_id: _completed.version_id,
+    })
+}
+
+pub async fn abort_multipart_upload(_client: &Client, _config: &GatewayConfig, _file_name: String, _upload_id: String) -> Result<(), GatewayError> {
+    let _args = AbortMultipartUploadArgs::new(&_config.bucket_name, &_file_name, &_upload_id)?;
+    match _client.abort_multipart_upload(&_args).await {
+        Ok(_) => Ok(()),
+        Err(error) => Err(error.into())
+    }
+}
+
+/// List the uploads in progress for a container, minio client does not expose `ListMultipartUploads`
+#[allow(clippy::too_many_arguments)]
+pub async fn get_list_multipart_uploads(_client: &Client, _config: &GatewayConfig, _client_id: Option<String>, _container_id: Option<String>, _container_type: Option<String>, _table_name: Option<String>, _column_name: Option<String>, _record_id: Option<String>, _user_id: Option<String>, _role_id: Option<String>) -> Result<MultipartUploadResponse, GatewayError> {
+    //  The separator avoids to match other containers starting with the same name, like record `10` and `100`
+    let _prefix = get_valid_path(_client_id, _container_id, _container_type, _table_name, _column_name, _record_id, _user_id, _role_id, true)? + "/";
+    let _region = _client.get_region(&_config.bucket_name, None).await?;
+    let mut _uploads: Vec<MultipartUpload> = Vec::new();
+    let mut _key_marker: Option<String> = None;
+    let mut _upload_id_marker: Option<String> = None;
+    loop {
+        let mut _headers = Multimap::new();
+        let mut _query_params = Multimap::new();
+        _query_params.insert("uploads".to_string(), String::new());
+        _query_params.insert("prefix".to_string(), _prefix.to_owned());
+        if let Some(_marker) = &_key_marker {
+            _query_params.insert("key-marker".to_string(), _marker.to_owned());
+        }
+        if let Some(_marker) = &_upload_id_marker {
+            _query_params.insert("upload-id-marker".to_string(), _marker.to_owned());
+        }
+        let _response = _client.execute(Method::GET, &_region, &mut _headers, &_query_params, Some(&_config.bucket_name), None, None).await?;
+        let _body = match _response.bytes().await {
+            Ok(value) => value,
+            Err(error) => return Err(GatewayError::Upstream(error.to_string()))
+        };
+        let mut _root = match Element::parse(_body.reader()) {
+            Ok(value) => value,
+            Err(error) => return Err(GatewayError::Upstream(error.to_string()))
+        };
+        while let Some(_upload) = _root.take_child("Upload") {
+            _uploads.push(MultipartUpload {
+                file_name: get_default_text(&_upload, "Key"),
+                upload_id: get_default_text(&_upload, "UploadId"),
+                initiated: get_option_text(&_upload, "Initiated"),
+            });
+        }
+        if !get_default_text(&_root, "IsTruncated").eq("true") {
+            break;
+        }
+        _key_marker = get_option_text(&_root, "NextKeyMarker");
+        _upload_id_marker = get_option_text(&_root, "NextUploadIdMarker");
+    }
+    Ok(MultipartUploadResponse {
+        parent_folder: Some(_prefix),
+        uploads: _uploads,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_part(_number: u16, _etag: &str) -> Part {
+        Part {
+            number: _number,
+            etag: _etag.to_string(),
+        }
+    }
+
+    fn get_uploaded_parts() -> HashMap<u16, (String, usize)> {
+        HashMap::from([(1, ("a".to_string(), 5242880)), (2, ("b".to_string(), 1024)), (3, ("c".to_string(), 10))])
+    }
+
+    #[test]
+    fn sums_the_parts_to_complete() {
+        assert_eq!(get_parts_size(&get_uploaded_parts(), &[get_part(1, "a"), get_part(2, "b")]).unwrap(), 5243904);
+        assert_eq!(get_parts_size(&get_uploaded_parts(), &[get_part(3, "c")]).unwrap(), 10);
+    }
+
+    #[test]
+    fn rejects_parts_not_uploaded() {
+        //  Missing part and a part uploaded again with other content
+        assert!(matches!(get_parts_size(&get_uploaded_parts(), &[get_part(1, "a"), get_part(4, "d")]), Err(GatewayError::Validation(_))));
+        assert!(matches!(get_parts_size(&get_uploaded_parts(), &[get_part(2, "other")]), Err(GatewayError::Validation(_))));
+    }
+}
@@ -1,217 +1,288 @@
 use std::collections::HashMap;
-use std::{env, io::Error, io::ErrorKind};
-use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 
+use bytes::{Buf, Bytes};
+use futures_util::Stream;
 use http::Method;
-use minio::s3::args::{GetPresignedObjectUrlArgs, ListObjectsV2Args, RemoveObjectArgs};
+use minio::s3::args::{GetPresignedObjectUrlArgs, ListObjectsV2Args, PostPolicy, RemoveObjectArgs, StatObjectArgs, DEFAULT_EXPIRY_SECONDS, MAX_PART_SIZE};
 use minio::s3::client::Client;
-use minio::s3::creds::StaticProvider;
-use minio::s3::http::BaseUrl;
-use minio::s3::response::ListObjectsV2Response;
+use minio::s3::response::{ListObjectsV2Response, StatObjectResponse};
+use minio::s3::types::Item;
+use minio::s3::utils::{get_default_text, md5sum_hash, urlencode, utc_now, Multimap};
 use regex::Regex;
 use serde::Serialize;
 use std::path::Path;
+use xmltree::{Element, XMLNode};
 
-fn get_valid_path(_client_id: Option<String>, _container_id: Option<String>, _container_type: Option<String>, _table_name: Option<String>, _column_name: Option<String>, _record_id: Option<String>, _user_id: Option<String>, _role_id: Option<String>, _include_access: bool) -> Result<String, std::io::Error> {
+use crate::config::{CollisionPolicy, GatewayConfig, UploadPolicy};
+use crate::controller::antivirus::{put_pending_scan_marker, scan_upload_stream};
+use crate::controller::metadata::{get_display_name, ObjectMetadata};
+use crate::controller::sniffing::{get_upload_content_type, sniff_stream};
+use crate::controller::thumbnail::remove_thumbnails;
+use crate::controller::trash::move_objects_to_trash;
+use crate::error::GatewayError;
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn get_valid_path(_client_id: Option<String>, _container_id: Option<String>, _container_type: Option<String>, _table_name: Option<String>, _column_name: Option<String>, _record_id: Option<String>, _user_id: Option<String>, _role_id: Option<String>, _include_access: bool) -> Result<String, GatewayError> {
     if _client_id.to_owned().is_none() {
 		log::error!("Client ID is Mandatory");
-        return Err(Error::new(ErrorKind::InvalidData.into(), "Client ID is Mandatory"))
+        return Err(GatewayError::Validation("Client ID is Mandatory".to_owned()))
     }
     if _container_type.to_owned().is_none() {
         log::error!("Container Type is Mandatory");
-        return Err(Error::new(ErrorKind::InvalidData.into(), "Container Type is Mandatory"))
+        return Err(GatewayError::Validation("Container Type is Mandatory".to_owned()))
     }
 
     if _record_id.to_owned().is_some() && _table_name.to_owned().is_none() {
 		log::error!("Table Name is Mandatory");
-		return Err(Error::new(ErrorKind::InvalidData.into(), "Table Name is Mandatory"))
+		return Err(GatewayError::Validation("Table Name is Mandatory".to_owned()))
     }
     if _record_id.to_owned().is_none() && _table_name.to_owned().is_some() {
 		log::error!("Record ID is Mandatory");
-        return Err(Error::new(ErrorKind::InvalidData.into(), "Record ID is Mandatory"))
+        return Err(GatewayError::Validation("Record ID is Mandatory".to_owned()))
     }
     if _column_name.to_owned().is_some() && _table_name.to_owned().is_none() {
 		log::error!("Table Name is Mandatory");
-        return Err(Error::new(ErrorKind::InvalidData.into(), "Table Name is Mandatory"))
+        return Err(GatewayError::Validation("Table Name is Mandatory".to_owned()))
     }
     if !matches!(_container_type.clone().unwrap().as_ref(), "window" | "process" | "report" | "browser" | "form" | "application" | "resource" | "attachment") {
 		log::error!("Invalid Container Type");
-        return Err(Error::new(ErrorKind::InvalidData.into(), "Invalid Container Type"))
+        return Err(GatewayError::Validation("Invalid Container Type".to_owned()))
     }
     if _container_id.is_none() && !_container_type.to_owned().unwrap().eq("attachment") {
 		log::error!("Container ID is Mandatory");
-		return Err(Error::new(ErrorKind::InvalidData.into(), "Container ID is Mandatory"))
+		return Err(GatewayError::Validation("Container ID is Mandatory".to_owned()))
 	}
-    if _table_name.is_none() || _record_id.is_none() {
-        if _container_type.to_owned().unwrap().eq("attachment") {
-			log::error!("Invalid Container Type (Mandatory Record ID and Table Name)");
-            return Err(Error::new(ErrorKind::InvalidData.into(), "Invalid Container Type (Mandatory Record ID and Table Name)"))
-        }
+    if (_table_name.is_none() || _record_id.is_none()) && _container_type.to_owned().unwrap().eq("attachment") {
+		log::error!("Invalid Container Type (Mandatory Record ID and Table Name)");
+		return Err(GatewayError::Validation("Invalid Container Type (Mandatory Record ID and Table Name)".to_owned()))
     }
     //  Client
     let mut _folder = get_valid_path_name(_client_id.to_owned().unwrap());
-    _folder.push_str("/");
+    _folder.push('/');
     //  Validate if is private access
     if _include_access && (_user_id.is_some() || _role_id.is_some()) {
-        if _user_id.is_some() {
+        if let Some(_user) = _user_id {
             _folder.push_str("user");
-            _folder.push_str("/");
-            _folder.push_str(&get_valid_path_name(_user_id.unwrap()));
+            _folder.push('/');
+            _folder.push_str(&get_valid_path_name(_user));
         } else {
             _folder.push_str("role");
-            _folder.push_str("/");
+            _folder.push('/');
             _folder.push_str(&get_valid_path_name(_role_id.unwrap()));
         }
-        _folder.push_str("/");
+        _folder.push('/');
     } else {
         _folder.push_str("client");
-        _folder.push_str("/");
+        _folder.push('/');
     }
 
     //  Container Type
     _folder.push_str(&get_valid_path_name(_container_type.unwrap()));
 	//  Container ID
-	if _container_id.is_some() {
-		_folder.push_str("/");
-        _folder.push_str(&get_valid_path_name(_container_id.unwrap()));
+	if let Some(_container) = _container_id {
+		_folder.push('/');
+        _folder.push_str(&get_valid_path_name(_container));
 	}
     //  Table Name
     if _table_name.to_owned().is_some() {
-        _folder.push_str("/");
+        _folder.push('/');
         _folder.push_str(&get_valid_path_name(_table_name.unwrap()));
-        _folder.push_str("/");
+        _folder.push('/');
         _folder.push_str(&get_valid_path_name(_record_id.unwrap()));
     }
     //  Column
     if _column_name.to_owned().is_some() {
-        _folder.push_str("/");
+        _folder.push('/');
         _folder.push_str(&get_valid_path_name(_column_name.unwrap()));
     }
-    let _final_folder = _folder.to_owned().to_lowercase();
     Ok(_folder.to_owned().to_lowercase())
 }
 
-fn get_valid_path_name(_value: String) -> String {
+pub(crate) fn get_valid_path_name(_value: String) -> String {
     let regex = Regex::new(r"[^A-Za-z0-9-]").unwrap();
     regex.replace_all(&_value, "_").to_string()
 }
 
-fn get_valid_file_path(_value: String) -> String {
-	let regex = Regex::new(r"[^A-Za-z0-9.-_]").unwrap();
-    regex.replace_all(&_value, "_").to_string()
+/// Owner segments of an object key built by `get_valid_path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourcePath {
+    pub client_id: String,
+    pub user_id: Option<String>,
+    pub role_id: Option<String>,
+    pub container_type: String,
+}
+
+/// Key of an object inside a folder of its client `<client>/<folder>/<key without client>`, like the trash or the thumbnails
+pub fn get_client_folder_file_name(_file_name: &str, _folder: &str) -> Result<String, GatewayError> {
+    match _file_name.split_once('/') {
+        Some((_client_id, _path)) if !_client_id.is_empty() && !_path.is_empty() => Ok(format!("{}/{}/{}", _client_id, _folder, _path)),
+        _ => {
+            log::error!("Invalid File Name {:?}", _file_name);
+            Err(GatewayError::Validation(format!("Invalid File Name `{}`", _file_name)))
+        }
+    }
+}
+
+/// Parse an object key back into its client, user or role and container type
+pub fn get_resource_path(_file_name: &str) -> Result<ResourcePath, GatewayError> {
+    let _segments = _file_name.split('/').collect::<Vec<&str>>();
+    if _segments.iter().any(|_segment| _segment.is_empty() || _segment.eq(&".") || _segment.eq(&"..")) {
+        log::error!("Invalid File Name {:?}", _file_name);
+        return Err(GatewayError::Validation("Invalid File Name".to_owned()))
+    }
+    let (_user_id, _role_id, _container_type) = match _segments.as_slice() {
+        [_, "client", _container_type, ..] => (None, None, _container_type),
+        [_, "user", _user_id, _container_type, ..] => (Some(_user_id.to_string()), None, _container_type),
+        [_, "role", _role_id, _container_type, ..] => (None, Some(_role_id.to_string()), _container_type),
+        _ => {
+            log::error!("File Name {:?} is not inside a container", _file_name);
+            return Err(GatewayError::Validation("File Name is not inside a container".to_owned()))
+        }
+    };
+    Ok(ResourcePath {
+        client_id: _segments[0].to_string(),
+        user_id: _user_id,
+        role_id: _role_id,
+        container_type: _container_type.to_string(),
+    })
+}
+
+/// The separators are replaced too, so the name is always a single segment of the key
+fn get_valid_file_path(_value: String) -> Result<String, GatewayError> {
+	let regex = Regex::new(r"[^A-Za-z0-9._-]").unwrap();
+    let _file_path = regex.replace_all(&_value, "_").to_string();
+    if matches!(_file_path.as_str(), "" | "." | "..") {
+        log::error!("Invalid File Name {:?}", _value);
+        return Err(GatewayError::Validation(format!("Invalid File Name `{}`", _value)))
+    }
+    Ok(_file_path)
 }
 
-pub fn get_valid_file_name(_client_id: Option<String>, _container_id: Option<String>, _file_name: Option<String>, _container_type: Option<String>, _table_name: Option<String>, _column_name: Option<String>, _record_id: Option<String>, _user_id: Option<String>, _role_id: Option<String>) -> Result<String, std::io::Error> {
+#[allow(clippy::too_many_arguments)]
+pub fn get_valid_file_name(_client_id: Option<String>, _container_id: Option<String>, _file_name: Option<String>, _container_type: Option<String>, _table_name: Option<String>, _column_name: Option<String>, _record_id: Option<String>, _user_id: Option<String>, _role_id: Option<String>) -> Result<String, GatewayError> {
     if _file_name.to_owned().is_none() {
 		log::error!("File Name is Mandatory");
-        return Err(Error::new(ErrorKind::InvalidData.into(), "File Name is Mandatory"))
+        return Err(GatewayError::Validation("File Name is Mandatory".to_owned()))
     }
     let _value = get_valid_path(_client_id, _container_id, _container_type, _table_name, _column_name, _record_id, _user_id, _role_id, true);
     match _value {
         Ok(_folder_name) => {
             let mut _valid_file_name = _folder_name;
-            _valid_file_name.push_str("/");
-            _valid_file_name.push_str(&get_valid_file_path(_file_name.unwrap()));
+            _valid_file_name.push('/');
+            _valid_file_name.push_str(&get_valid_file_path(_file_name.unwrap())?);
             let _final_file_name = _valid_file_name.to_owned().to_lowercase();
             Ok(_final_file_name)
         },
-        Err(error) => Err(Error::new(ErrorKind::InvalidData.into(), error))
+        Err(error) => Err(error)
     }
 }
 
-pub async fn get_list_objects(_client_id: Option<String>, _container_id: Option<String>, _container_type: Option<String>, _table_name: Option<String>, _column_name: Option<String>, _record_id: Option<String>, _user_id: Option<String>, _role_id: Option<String>) -> Result<ResourceResponse, std::io::Error> {
-    let _s3_url =  match env::var("S3_URL") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `S3_URL` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _bucket_name =  match env::var("BUCKET_NAME") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `BUCKET_NAME` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _api_key =  match env::var("API_KEY") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `API_KEY` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _secret_key =  match env::var("SECRET_KEY") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `SECRET_KEY` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _manage_https =  match env::var("MANAGE_HTTPS") {
-        Ok(value) => {
-            value.eq("Y")
-        },
-        Err(_) => {
-            log::info!("Variable `MANAGE_HTTPS` Not found");
-            false
-        },
-    };
-    let _ssl_cert_file =  match env::var("SSL_CERT_FILE") {
-        Ok(value) => value,
-        Err(_) => {
-            log::info!("Variable `SSL_CERT_FILE` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let mut _base_url: BaseUrl = match BaseUrl::from_str(&_s3_url) {
-        Ok(url) => url,
-        Err(error) => return Err(Error::new(ErrorKind::InvalidData.into(), error)),
-    };
-    _base_url.https = _manage_https;
-    let _cert_file_path= match _manage_https {
-        true => Some(Path::new(&_ssl_cert_file)),
-        false => None,
-    };
-    let static_provider: StaticProvider = StaticProvider::new(
-        &_api_key, 
-        &_secret_key, None);
+/// Pagination and depth of `get_list_objects`
+#[derive(Debug, Clone)]
+pub struct ListObjectsOptions {
+    /// Max objects by page, S3 does not return more than 1000
+    pub limit: Option<u16>,
+    pub continuation_token: Option<String>,
+    /// Follow the continuation tokens and return all the objects at once
+    pub fetch_all: bool,
+    /// List the whole tree, otherwise only the immediate children with sub folders as prefixes
+    pub recursive: bool,
+}
 
-    let client = Client::new(_base_url.clone(), Some(Box::new(static_provider)), _cert_file_path, None).unwrap();
+impl Default for ListObjectsOptions {
+    fn default() -> Self {
+        ListObjectsOptions {
+            limit: None,
+            continuation_token: None,
+            fetch_all: false,
+            recursive: true,
+        }
+    }
+}
+
+/// S3 does not return more than 1000 keys by page
+const MAX_LIST_LIMIT: u16 = 1000;
+
+#[allow(clippy::too_many_arguments)]
+pub async fn get_list_objects(_client: &Client, _config: &GatewayConfig, _client_id: Option<String>, _container_id: Option<String>, _container_type: Option<String>, _table_name: Option<String>, _column_name: Option<String>, _record_id: Option<String>, _user_id: Option<String>, _role_id: Option<String>, _options: ListObjectsOptions) -> Result<ResourceResponse, GatewayError> {
     let _value = get_valid_path(_client_id, _container_id, _container_type, _table_name, _column_name, _record_id, _user_id, _role_id, true);
     let _prefix = match _value {
-        Ok(_folder_name) => Some(_folder_name),
+        //  The slash keeps out the siblings with the same start, like record `10` when listing record `1`
+        Ok(_folder_name) => Some(_folder_name + "/"),
         Err(error) => {
             log::warn!("Error Getting path {:?}", error);
-            return Err(Error::new(ErrorKind::InvalidData.into(), error))
+            return Err(error)
         }
     };
-    let mut _args = ListObjectsV2Args::new(&_bucket_name).unwrap();
-    _args.prefix = _prefix.as_deref();
-    match client
-            .list_objects_v2(&_args)
-            .await {
-        Ok(value) => Ok(ResourceResponse::new(value)),
-        Err(error) => Err(Error::new(ErrorKind::InvalidData.into(), error)),
+    list_objects_by_prefix(_client, _config, _prefix, _options).await
+}
+
+/// List the objects under a prefix, following the continuation tokens when all the pages are requested
+pub async fn list_objects_by_prefix(_client: &Client, _config: &GatewayConfig, _prefix: Option<String>, _options: ListObjectsOptions) -> Result<ResourceResponse, GatewayError> {
+    let _delimiter = match _options.recursive {
+        true => None,
+        false => Some("/"),
+    };
+    if let Some(_limit) = _options.limit {
+        if _limit == 0 || _limit > MAX_LIST_LIMIT {
+            log::error!("Invalid Limit {}", _limit);
+            return Err(GatewayError::Validation(format!("Limit must be between 1 and {}", MAX_LIST_LIMIT)))
+        }
+    }
+    let mut _continuation_token = _options.continuation_token;
+    let mut _response: Option<ResourceResponse> = None;
+    loop {
+        let mut _args = ListObjectsV2Args::new(&_config.bucket_name)?;
+        _args.prefix = _prefix.as_deref();
+        _args.delimiter = _delimiter;
+        _args.max_keys = _options.limit;
+        _args.continuation_token = _continuation_token;
+        //  Only supported by MinIO, it is required to get the display name
+        _args.include_user_metadata = true;
+        let _page = match _client
+                .list_objects_v2(&_args)
+                .await {
+            Ok(value) => ResourceResponse::new(value),
+            Err(error) => return Err(error.into()),
+        };
+        _continuation_token = _page.next_continuation_token.to_owned();
+        _response = Some(match _response {
+            Some(mut _previous) => {
+                _previous.append(_page);
+                _previous
+            },
+            None => _page,
+        });
+        if !_options.fetch_all || _continuation_token.is_none() {
+            break;
+        }
     }
-    
+    Ok(_response.unwrap())
 }
 
 #[derive(Serialize, Debug, Clone)]
 pub struct PresignedObject {
     pub url: Option<String>,
-    pub file_name: Option<String>
+    pub file_name: Option<String>,
+    /// `PUT` of the body to the URL, or `POST` of a form with the `form_data` fields and the `file`
+    pub method: Option<String>,
+    pub form_data: Option<HashMap<String, String>>,
 }
 
 #[derive(Serialize, Debug, Clone)]
 pub struct ResourceResponse {
     pub parent_folder: Option<String>,
-    pub resources: Option<Vec<Resource>>
+    pub resources: Option<Vec<Resource>>,
+    pub is_truncated: bool,
+    pub next_continuation_token: Option<String>,
 }
 
 #[derive(Serialize, Debug, Clone)]
 pub struct Resource {
     pub name: String,
+    /// Original name sent on upload
+    pub display_name: Option<String>,
     pub last_modified: Option<String>,
     pub etag: Option<String>, // except DeleteMarker
     pub owner_name: Option<String>,
@@ -226,192 +297,652 @@ pub struct Resource {
     pub content_type: Option<String>,
 }
 
+impl Resource {
+    /// Resource of a listing entry
+    pub fn from_item(_content: &Item) -> Self {
+        let _file_name = _content.to_owned().name;
+        //  Folders from delimited listing and delete markers have no content
+        let _has_content = !_content.is_prefix && !_content.is_delete_marker;
+        //  MinIO returns the `Content-Type` stored on upload with the user metadata
+        let _stored_content_type = _content.user_metadata.as_ref().and_then(|_user_metadata| {
+            _user_metadata.iter()
+                .find(|(_key, _)| _key.eq_ignore_ascii_case("content-type"))
+                .map(|(_, _value)| _value.to_owned())
+        });
+        let _content_type = match _has_content {
+            true => Some(_stored_content_type.unwrap_or_else(|| mime_guess::from_path(Path::new(&_file_name)).first_or_octet_stream().to_string())),
+            false => None,
+        };
+        let _display_name = match _content.is_prefix {
+            true => None,
+            false => Some(get_display_name(&_file_name, _content.user_metadata.as_ref())),
+        };
+        Resource { 
+            display_name: _display_name,
+            last_modified: _content.last_modified.map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string()),
+            name: _file_name, 
+            etag: _content.to_owned().etag, 
+            owner_name: _content.to_owned().owner_name, 
+            size: _content.size, 
+            storage_class: _content.to_owned().storage_class, 
+            is_latest: _content.is_latest, 
+            version_id: _content.to_owned().version_id, 
+            user_metadata: _content.to_owned().user_metadata,
+            is_prefix: _content.is_prefix, 
+            is_delete_marker: _content.is_delete_marker, 
+            encoding_type: _content.to_owned().encoding_type,
+            content_type: _content_type
+        }
+    }
+
+    /// Resource of a single object, the content type is the one stored on upload
+    pub fn from_stat(_stat: &StatObjectResponse) -> Self {
+        let _content_type = match _stat.headers.get("content-type").and_then(|value| value.to_str().ok()) {
+            Some(value) => value.to_owned(),
+            None => mime_guess::from_path(Path::new(&_stat.object_name)).first_or_octet_stream().to_string(),
+        };
+        Resource {
+            name: _stat.object_name.to_owned(),
+            display_name: Some(get_display_name(&_stat.object_name, Some(&_stat.user_metadata))),
+            last_modified: _stat.last_modified.map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string()),
+            etag: Some(_stat.etag.to_owned()),
+            owner_name: None,
+            size: Some(_stat.size),
+            storage_class: _stat.headers.get("x-amz-storage-class").and_then(|value| value.to_str().ok()).map(|value| value.to_owned()),
+            is_latest: true,
+            version_id: _stat.version_id.to_owned(),
+            user_metadata: Some(_stat.user_metadata.to_owned()),
+            is_prefix: false,
+            is_delete_marker: _stat.delete_marker.unwrap_or(false),
+            encoding_type: _stat.headers.get("content-encoding").and_then(|value| value.to_str().ok()).map(|value| value.to_owned()),
+            content_type: Some(_content_type),
+        }
+    }
+}
+
 impl ResourceResponse {
     pub fn new(_data: ListObjectsV2Response) -> Self {
         ResourceResponse {
             parent_folder: _data.prefix,
-            resources: Some(_data.contents.iter().map(|_content| {
-                let _file_name = _content.to_owned().name;
-                let _content_type = mime_guess::from_path(Path::new(&_file_name)).first_or_octet_stream();
-                Resource { 
-                    last_modified: match _content.last_modified {
-                        Some(date) => Some(date.format("%Y-%m-%d %H:%M:%S").to_string()),
-                        None => None
-                    },
-                    name: _file_name, 
-                    etag: _content.to_owned().etag, 
-                    owner_name: _content.to_owned().owner_name, 
-                    size: _content.size, 
-                    storage_class: _content.to_owned().storage_class, 
-                    is_latest: _content.is_latest, 
-                    version_id: _content.to_owned().version_id, 
-                    user_metadata: _content.to_owned().user_metadata,
-                    is_prefix: _content.is_prefix, 
-                    is_delete_marker: _content.is_delete_marker, 
-                    encoding_type: _content.to_owned().encoding_type,
-                    content_type: Some(_content_type.to_string())
-                }
-            }).collect::<Vec<Resource>>()),
+            resources: Some(_data.contents.iter().map(Resource::from_item).collect::<Vec<Resource>>()),
+            is_truncated: _data.is_truncated,
+            next_continuation_token: _data.next_continuation_token,
         }
     }
+
+    /// Add the resources of the next page
+    pub fn append(&mut self, _page: ResourceResponse) {
+        if let Some(_resources) = _page.resources {
+            self.resources.get_or_insert_with(Vec::new).extend(_resources);
+        }
+        self.is_truncated = _page.is_truncated;
+        self.next_continuation_token = _page.next_continuation_token;
+    }
 }
 
 
-pub async fn request_signed_url(_file_name: String, _method: Method, _seconds: Option<u32>) -> Result<String, std::io::Error> {
-    let _s3_url =  match env::var("S3_URL") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `S3_URL` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _bucket_name =  match env::var("BUCKET_NAME") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `BUCKET_NAME` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _api_key =  match env::var("API_KEY") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `API_KEY` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _secret_key =  match env::var("SECRET_KEY") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `SECRET_KEY` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _manage_https =  match env::var("MANAGE_HTTPS") {
-        Ok(value) => {
-            value.eq("Y")
-        },
-        Err(_) => {
-            log::info!("Variable `MANAGE_HTTPS` Not found");
-            false
-        },
-    };
-    let _ssl_cert_file =  match env::var("SSL_CERT_FILE") {
-        Ok(value) => value,
-        Err(_) => {
-            log::info!("Variable `SSL_CERT_FILE` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let mut _base_url: BaseUrl = match BaseUrl::from_str(&_s3_url) {
-        Ok(url) => url,
-        Err(error) => return Err(Error::new(ErrorKind::InvalidData.into(), error)),
-    };
-    _base_url.https = _manage_https;
-    let _cert_file_path= match _manage_https {
-        true => Some(Path::new(&_ssl_cert_file)),
-        false => None,
-    };
-    let static_provider: StaticProvider = StaticProvider::new(
-        &_api_key, 
-        &_secret_key, None);
-
-    let client = Client::new(_base_url.clone(), Some(Box::new(static_provider)), _cert_file_path, None).unwrap();
-    let args_to_match = GetPresignedObjectUrlArgs::new(
-        &_bucket_name,
-        _file_name.as_str(),
-        _method,
+pub async fn delete_object(_client: &Client, _config: &GatewayConfig, _file_name: String) -> Result<(), GatewayError> {
+    let args_to_match = RemoveObjectArgs::new(
+        &_config.bucket_name,
+        _file_name.as_str()
     );
 
     match args_to_match {
         Ok(value) => {
-            let mut _presigned_parameters = value;
-            match _seconds {
-                Some(seconds) => _presigned_parameters.expiry_seconds = Some(seconds),
-                None => {
-
-                }
-            };
-            match client.get_presigned_object_url(&_presigned_parameters).await {
-                Ok(url) => Ok(url.url),
-                Err(error) => return Err(Error::new(ErrorKind::InvalidData.into(), error))
+            match _client.remove_object(&value).await {
+                Ok(_) => {
+                    remove_thumbnails(_client, _config, std::slice::from_ref(&_file_name)).await;
+                    Ok(())
+                },
+                Err(error) => Err(error.into())
             }
         },
-        Err(error) => return Err(Error::new(ErrorKind::InvalidData.into(), error))
+        Err(error) => Err(error.into())
     }
 }
 
-pub async fn delete_object(_file_name: String) -> Result<(), std::io::Error> {
-    let _s3_url =  match env::var("S3_URL") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `S3_URL` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _bucket_name =  match env::var("BUCKET_NAME") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `BUCKET_NAME` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _api_key =  match env::var("API_KEY") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `API_KEY` Not found");
-            "".to_owned()
-        }.to_owned(),
-    };
-    let _secret_key =  match env::var("SECRET_KEY") {
-        Ok(value) => value,
-        Err(_) => {
-			log::warn!("Variable `SECRET_KEY` Not found");
-            "".to_owned()
-        }.to_owned(),
+#[derive(Serialize, Debug, Clone)]
+pub struct UploadedObject {
+    pub file_name: String,
+    pub size: usize,
+    pub etag: Option<String>,
+    pub version_id: Option<String>,
+}
+
+/// Presigned GET of the current or a previous version, answered by S3 with the `Content-Disposition` sent
+pub async fn request_signed_get_url(_client: &Client, _config: &GatewayConfig, _file_name: String, _version_id: Option<String>, _content_disposition: Option<String>, _seconds: Option<u32>) -> Result<String, GatewayError> {
+    let mut _query_params = Multimap::new();
+    if let Some(_content_disposition) = _content_disposition {
+        _query_params.insert("response-content-disposition".to_string(), _content_disposition);
+    }
+    let mut _args = GetPresignedObjectUrlArgs::new(&_config.bucket_name, &_file_name, Method::GET)?;
+    _args.extra_query_params = Some(&_query_params);
+    _args.version_id = _version_id.as_deref();
+    if let Some(seconds) = _seconds {
+        _args.expiry_seconds = Some(seconds);
+    }
+    match _client.get_presigned_object_url(&_args).await {
+        Ok(value) => Ok(value.url),
+        Err(error) => Err(error.into())
+    }
+}
+
+/// Presigned PUT with the user metadata and tags the uploaded object must have
+pub async fn request_signed_put_url(_client: &Client, _config: &GatewayConfig, _file_name: String, _metadata: &ObjectMetadata, _seconds: Option<u32>) -> Result<String, GatewayError> {
+    _metadata.validate()?;
+    put_pending_scan_marker(_client, _config, &_file_name, _metadata.user_metadata.as_ref()).await?;
+    let _query_params = _metadata.to_headers();
+    let mut _args = GetPresignedObjectUrlArgs::new(&_config.bucket_name, &_file_name, Method::PUT)?;
+    _args.extra_query_params = Some(&_query_params);
+    if let Some(seconds) = _seconds {
+        _args.expiry_seconds = Some(seconds);
+    }
+    match _client.get_presigned_object_url(&_args).await {
+        Ok(value) => Ok(value.url),
+        Err(error) => Err(error.into())
+    }
+}
+
+/// Upload policy of the container type of an object key
+pub fn get_object_upload_policy<'a>(_config: &'a GatewayConfig, _file_name: &str) -> Option<&'a UploadPolicy> {
+    let _resource_path = get_resource_path(_file_name).ok()?;
+    _config.get_upload_policy(Some(&_resource_path.container_type))
+}
+
+/// Presigned POST for browser forms, the size and content type of the upload are conditions of the signed policy
+#[allow(clippy::too_many_arguments)]
+pub async fn request_signed_post_form(_client: &Client, _config: &GatewayConfig, _file_name: String, _metadata: &ObjectMetadata, _content_type: Option<String>, _max_size: Option<usize>, _upload_policy: Option<&UploadPolicy>, _seconds: Option<u32>) -> Result<PresignedObject, GatewayError> {
+    _metadata.validate()?;
+    let mut _upload_policy = _upload_policy.cloned().unwrap_or_default();
+    //  The requested size can only be lower than the size of the policy
+    _upload_policy.max_size = match (_upload_policy.max_size, _max_size) {
+        (Some(_policy_size), Some(_requested_size)) => Some(_policy_size.min(_requested_size)),
+        (_policy_size, _requested_size) => _policy_size.or(_requested_size),
     };
-    let _manage_https =  match env::var("MANAGE_HTTPS") {
-        Ok(value) => {
-            value.eq("Y")
+    let _expiration = utc_now() + chrono::Duration::seconds(_seconds.unwrap_or(DEFAULT_EXPIRY_SECONDS).into());
+    let mut _policy = PostPolicy::new(&_config.bucket_name, &_expiration)?;
+    let mut _form_data: HashMap<String, String> = HashMap::new();
+    _policy.add_equals_condition("key", &_file_name)?;
+    _form_data.insert("key".to_string(), _file_name.to_owned());
+    let _allowed_content_types = _upload_policy.allowed_content_types.to_owned().unwrap_or_default();
+    match (_content_type, _allowed_content_types.as_slice()) {
+        (Some(_content_type), _) => {
+            _upload_policy.validate(Some(&_content_type), None)?;
+            _policy.add_equals_condition("Content-Type", &_content_type)?;
+            _form_data.insert("Content-Type".to_string(), _content_type);
+        },
+        //  Any type, the browser sends the type of the file
+        (None, []) => _policy.add_starts_with_condition("Content-Type", "")?,
+        (None, [_allowed]) => match _allowed.strip_suffix('*') {
+            Some(_prefix) => _policy.add_starts_with_condition("Content-Type", _prefix)?,
+            None => {
+                _policy.add_equals_condition("Content-Type", _allowed)?;
+                _form_data.insert("Content-Type".to_string(), _allowed.to_owned());
+            }
+        },
+        //  The policy can not allow a list of types
+        (None, _) => {
+            log::error!("Content Type is Mandatory for `{}`", _file_name);
+            return Err(GatewayError::Validation(format!("Content Type is Mandatory, allowed types are `{}`", _allowed_content_types.join("`, `"))))
+        }
+    }
+    if let Some(_max_size) = _upload_policy.max_size {
+        _policy.add_content_length_range_condition(0, _max_size)?;
+    }
+    //  All the fields of the form must be conditions of the policy
+    for (_name, _value) in _metadata.to_form_fields() {
+        _policy.add_equals_condition(&_name, &_value)?;
+        _form_data.insert(_name, _value);
+    }
+    put_pending_scan_marker(_client, _config, &_file_name, _metadata.user_metadata.as_ref()).await?;
+    _form_data.extend(_client.get_presigned_post_form_data(&_policy).await?);
+    let _region = _client.get_region(&_config.bucket_name, None).await?;
+    Ok(PresignedObject {
+        url: Some(_config.get_bucket_url(&_region)?),
+        file_name: Some(_file_name),
+        method: Some(Method::POST.to_string()),
+        form_data: Some(_form_data),
+    })
+}
+
+/// Stream a request body to S3 through a presigned PUT, the body is never held in memory
+#[allow(clippy::too_many_arguments)]
+pub async fn put_object_stream<S>(_client: &Client, _http_client: &reqwest::Client, _config: &GatewayConfig, _file_name: String, _content_type: Option<String>, _content_length: usize, _metadata: &ObjectMetadata, _stream: S) -> Result<UploadedObject, GatewayError>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync + 'static,
+{
+    let (_detected_type, _stream) = sniff_stream(_stream).await?;
+    //  The stored type replaces the type sent by the client, the policy is validated with it
+    let _content_type = get_upload_content_type(&_file_name, _detected_type.as_ref(), _content_type, _config.reject_content_type_mismatch)?;
+    if let Some(_policy) = get_object_upload_policy(_config, &_file_name) {
+        _policy.validate(Some(&_content_type), Some(_content_length))?;
+    }
+    let _scan_failure = Arc::new(Mutex::new(None));
+    let (_metadata, _stream) = scan_upload_stream(_config, _metadata, _stream, _scan_failure.to_owned()).await?;
+    let _url = request_signed_put_url(_client, _config, _file_name.to_owned(), &_metadata, None).await?;
+    let _response = _http_client.put(_url)
+        .header(http::header::CONTENT_LENGTH, _content_length)
+        .header(http::header::CONTENT_TYPE, _content_type)
+        .body(reqwest::Body::wrap_stream(_stream))
+        .send()
+        .await;
+    //  The upload is aborted when the antivirus finds a threat, S3 does not store the incomplete body
+    if let Some(error) = _scan_failure.lock().ok().and_then(|mut _failure| _failure.take()) {
+        log::error!("Upload of `{}` was aborted: {}", _file_name, error);
+        return Err(error)
+    }
+    match _response {
+        Ok(response) => {
+            let _status = response.status();
+            if !_status.is_success() {
+                let _message = response.text().await.unwrap_or_default();
+                log::error!("Error uploading `{}`: {} {}", _file_name, _status, _message);
+                return Err(match _status.as_u16() {
+                    403 => GatewayError::Forbidden(format!("Upload of `{}` was denied", _file_name)),
+                    404 => GatewayError::NotFound(format!("Bucket `{}` does not exist", _config.bucket_name)),
+                    _ => GatewayError::Upstream(format!("Error uploading `{}`: {}", _file_name, _status)),
+                })
+            }
+            let _headers = response.headers();
+            Ok(UploadedObject {
+                file_name: _file_name,
+                size: _content_length,
+                etag: _headers.get(http::header::ETAG)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.trim_matches('"').to_owned()),
+                version_id: _headers.get("x-amz-version-id")
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.to_owned()),
+            })
         },
-        Err(_) => {
-            log::info!("Variable `MANAGE_HTTPS` Not found");
-            false
+        Err(error) => {
+            log::error!("Error uploading `{}`: {}", _file_name, error);
+            Err(GatewayError::Upstream(format!("Error uploading `{}`: {}", _file_name, error)))
+        }
+    }
+}
+
+/// Store a body generated by the gateway, like the thumbnails, without the checks of the uploads
+pub async fn put_object_data(_client: &Client, _http_client: &reqwest::Client, _config: &GatewayConfig, _file_name: String, _content_type: &str, _data: Bytes) -> Result<(), GatewayError> {
+    let _url = request_signed_put_url(_client, _config, _file_name.to_owned(), &ObjectMetadata::default(), None).await?;
+    let _response = _http_client.put(_url)
+        .header(http::header::CONTENT_TYPE, _content_type)
+        .body(_data)
+        .send()
+        .await;
+    match _response {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => {
+            log::error!("Error storing `{}`: {}", _file_name, response.status());
+            Err(GatewayError::Upstream(format!("Error storing `{}`: {}", _file_name, response.status())))
+        },
+        Err(error) => {
+            log::error!("Error storing `{}`: {}", _file_name, error);
+            Err(GatewayError::Upstream(format!("Error storing `{}`: {}", _file_name, error)))
+        }
+    }
+}
+
+/// Open the object body through a presigned GET, the request headers like `Range` are forwarded to S3
+/// and a `304 Not Modified` answer is returned as is
+pub async fn get_object_stream(_client: &Client, _http_client: &reqwest::Client, _config: &GatewayConfig, _file_name: String, _version_id: Option<String>, _headers: Vec<(String, String)>) -> Result<reqwest::Response, GatewayError> {
+    let _url = request_signed_get_url(_client, _config, _file_name.to_owned(), _version_id, None, None).await?;
+    let mut _request = _http_client.get(_url);
+    for (_name, _value) in _headers {
+        _request = _request.header(_name, _value);
+    }
+    match _request.send().await {
+        Ok(response) => {
+            let _status = response.status();
+            if _status.is_success() || _status == reqwest::StatusCode::NOT_MODIFIED {
+                return Ok(response)
+            }
+            log::warn!("Error downloading `{}`: {}", _file_name, _status);
+            Err(match _status.as_u16() {
+                403 => GatewayError::Forbidden(format!("Download of `{}` was denied", _file_name)),
+                404 => GatewayError::NotFound(format!("Object `{}` does not exist", _file_name)),
+                412 => GatewayError::PreconditionFailed(format!("Precondition failed for `{}`", _file_name)),
+                416 => GatewayError::InvalidRange(format!("Requested range is not satisfiable for `{}`", _file_name)),
+                _ => GatewayError::Upstream(format!("Error downloading `{}`: {}", _file_name, _status)),
+            })
         },
+        Err(error) => {
+            log::error!("Error downloading `{}`: {}", _file_name, error);
+            Err(GatewayError::Upstream(format!("Error downloading `{}`: {}", _file_name, error)))
+        }
+    }
+}
+
+pub async fn get_object_stat(_client: &Client, _config: &GatewayConfig, _file_name: String, _version_id: Option<String>) -> Result<StatObjectResponse, GatewayError> {
+    let mut _args = StatObjectArgs::new(&_config.bucket_name, &_file_name)?;
+    _args.version_id = _version_id.as_deref();
+    match _client.stat_object(&_args).await {
+        Ok(value) => Ok(value),
+        Err(error) => Err(error.into())
+    }
+}
+
+/// Metadata of a single object without list its container
+pub async fn get_object_metadata(_client: &Client, _config: &GatewayConfig, _file_name: String, _version_id: Option<String>) -> Result<Resource, GatewayError> {
+    let _stat = get_object_stat(_client, _config, _file_name, _version_id).await?;
+    Ok(Resource::from_stat(&_stat))
+}
+
+/// Object without content used as marker, minio `put_object_api` future is not `Send`
+pub async fn put_empty_object(_client: &Client, _config: &GatewayConfig, _file_name: &str) -> Result<(), GatewayError> {
+    let _region = _client.get_region(&_config.bucket_name, None).await?;
+    let mut _headers = Multimap::new();
+    _headers.insert("Content-Type".to_string(), "application/octet-stream".to_string());
+    _client.execute(Method::PUT, &_region, &mut _headers, &Multimap::new(), Some(&_config.bucket_name), Some(_file_name), Some(&[])).await?;
+    Ok(())
+}
+
+/// `CopyObject` does not support objects larger than 5 GiB
+pub fn validate_copy_size(_source: &StatObjectResponse) -> Result<(), GatewayError> {
+    if _source.size > MAX_PART_SIZE {
+        log::error!("`{}` can not be copied, size {} exceeds {}", _source.object_name, _source.size, MAX_PART_SIZE);
+        return Err(GatewayError::Validation(format!("Files larger than {} bytes can not be copied, moved or updated", MAX_PART_SIZE)))
+    }
+    Ok(())
+}
+
+/// Server side copy of the stat version, the metadata is replaced with `_headers` when they are sent, minio `copy_object` future is not `Send`
+pub async fn copy_object(_client: &Client, _config: &GatewayConfig, _source: &StatObjectResponse, _file_name: &str, _headers: Option<Multimap>) -> Result<(), GatewayError> {
+    validate_copy_size(_source)?;
+    let _source_file_name = &_source.object_name;
+    let _region = _client.get_region(&_config.bucket_name, None).await?;
+    let mut _headers = match _headers {
+        Some(mut _headers) => {
+            _headers.insert("x-amz-metadata-directive".to_string(), "REPLACE".to_string());
+            _headers
+        },
+        None => Multimap::new(),
+    };
+    let _copy_source = _source_file_name.split('/')
+        .map(|_segment| urlencode(_segment).into_owned())
+        .collect::<Vec<String>>()
+        .join("/");
+    let _copy_source = match &_source.version_id {
+        Some(_version_id) => format!("/{}/{}?versionId={}", _config.bucket_name, _copy_source, urlencode(_version_id)),
+        None => format!("/{}/{}", _config.bucket_name, _copy_source),
     };
-    let _ssl_cert_file =  match env::var("SSL_CERT_FILE") {
+    _headers.insert("x-amz-copy-source".to_string(), _copy_source);
+    //  The copy fails if the source was replaced after the stat, like an upload with a presigned URL
+    _headers.insert("x-amz-copy-source-if-match".to_string(), get_quoted_etag(&_source.etag));
+    let _response = _client.execute(Method::PUT, &_region, &mut _headers, &Multimap::new(), Some(&_config.bucket_name), Some(_file_name), None).await?;
+    let _body = match _response.bytes().await {
         Ok(value) => value,
-        Err(_) => {
-            log::info!("Variable `SSL_CERT_FILE` Not found");
-            "".to_owned()
-        }.to_owned(),
+        Err(error) => return Err(GatewayError::Upstream(error.to_string()))
     };
-    let mut _base_url: BaseUrl = match BaseUrl::from_str(&_s3_url) {
-        Ok(url) => url,
-        Err(error) => return Err(Error::new(ErrorKind::InvalidData.into(), error)),
+    //  S3 can fail the copy after answer with 200, the error is sent in the body
+    match Element::parse(_body.reader()) {
+        Ok(_root) if _root.name.eq("Error") => {
+            let _message = get_default_text(&_root, "Message");
+            log::error!("Error copying `{}` to `{}`: {}", _source_file_name, _file_name, _message);
+            Err(GatewayError::Upstream(format!("Error copying `{}`: {}", _source_file_name, _message)))
+        },
+        Ok(_) => Ok(()),
+        Err(error) => Err(GatewayError::Upstream(error.to_string()))
+    }
+}
+
+/// ETag with quotes as expected by `If-Match`, minio removes them from the stat
+pub fn get_quoted_etag(_etag: &str) -> String {
+    format!("\"{}\"", _etag.trim_matches('"'))
+}
+
+/// Suffixes tried by the `rename` collision policy before reject the upload
+const MAX_RENAME_ATTEMPTS: u16 = 100;
+
+pub async fn is_object_exists(_client: &Client, _config: &GatewayConfig, _file_name: &str) -> Result<bool, GatewayError> {
+    match get_object_stat(_client, _config, _file_name.to_owned(), None).await {
+        Ok(_) => Ok(true),
+        Err(GatewayError::NotFound(_)) => Ok(false),
+        Err(error) => Err(error)
+    }
+}
+
+/// Keys tried by the `rename` policy in order, the suffix goes before the last extension
+fn get_rename_candidates(_file_name: &str) -> Vec<String> {
+    let _name_start = _file_name.rfind('/').map(|index| index + 1).unwrap_or(0);
+    let (_stem, _extension) = match _file_name[_name_start..].rfind('.') {
+        //  Hidden files like `.env` have no extension
+        Some(index) if index > 0 => _file_name.split_at(_name_start + index),
+        _ => (_file_name, ""),
     };
-    _base_url.https = _manage_https;
-    let _cert_file_path= match _manage_https {
-        true => Some(Path::new(&_ssl_cert_file)),
-        false => None,
+    (1..=MAX_RENAME_ATTEMPTS)
+        .map(|_attempt| format!("{}_{}{}", _stem, _attempt, _extension))
+        .collect()
+}
+
+/// Key to store an upload according to the collision policy, `rename` adds a numeric suffix like `invoice_1.pdf`
+pub async fn resolve_file_name_collision(_client: &Client, _config: &GatewayConfig, _file_name: String, _policy: CollisionPolicy) -> Result<String, GatewayError> {
+    if _policy == CollisionPolicy::Overwrite || !is_object_exists(_client, _config, &_file_name).await? {
+        return Ok(_file_name)
+    }
+    if _policy == CollisionPolicy::Reject {
+        log::warn!("Object `{}` already exists", _file_name);
+        return Err(GatewayError::Conflict(format!("File `{}` already exists", _file_name)))
+    }
+    for _candidate in get_rename_candidates(&_file_name) {
+        if !is_object_exists(_client, _config, &_candidate).await? {
+            return Ok(_candidate)
+        }
+    }
+    log::error!("No free name found for `{}` after {} attempts", _file_name, MAX_RENAME_ATTEMPTS);
+    Err(GatewayError::Conflict(format!("File `{}` already exists", _file_name)))
+}
+
+/// S3 deletes up to 1000 objects by request
+const MAX_DELETE_OBJECTS: usize = 1000;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeleteFailure {
+    pub file_name: String,
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DeleteObjectsResponse {
+    pub parent_folder: Option<String>,
+    pub deleted: Vec<String>,
+    pub errors: Vec<DeleteFailure>,
+}
+
+/// Delete a list of objects and their cached thumbnails, the objects that fail are reported instead of stop
+pub async fn delete_objects(_client: &Client, _config: &GatewayConfig, _file_names: Vec<String>) -> Result<DeleteObjectsResponse, GatewayError> {
+    let _response = delete_object_keys(_client, _config, _file_names).await?;
+    remove_thumbnails(_client, _config, &_response.deleted).await;
+    Ok(_response)
+}
+
+/// Delete a list of keys with the multi-object delete of S3, without touch the thumbnails
+pub async fn delete_object_keys(_client: &Client, _config: &GatewayConfig, _file_names: Vec<String>) -> Result<DeleteObjectsResponse, GatewayError> {
+    let mut _response = DeleteObjectsResponse::default();
+    for _chunk in _file_names.chunks(MAX_DELETE_OBJECTS) {
+        let mut _root = Element::new("Delete");
+        for _file_name in _chunk {
+            let mut _key = Element::new("Key");
+            _key.children.push(XMLNode::Text(_file_name.to_owned()));
+            let mut _object = Element::new("Object");
+            _object.children.push(XMLNode::Element(_key));
+            _root.children.push(XMLNode::Element(_object));
+        }
+        let mut _body: Vec<u8> = Vec::new();
+        if let Err(error) = _root.write(&mut _body) {
+            return Err(GatewayError::Upstream(error.to_string()))
+        }
+        let _region = _client.get_region(&_config.bucket_name, None).await?;
+        let mut _headers = Multimap::new();
+        _headers.insert("Content-Type".to_string(), "application/xml".to_string());
+        _headers.insert("Content-MD5".to_string(), md5sum_hash(&_body));
+        let mut _query_params = Multimap::new();
+        _query_params.insert("delete".to_string(), String::new());
+        let _result = _client.execute(Method::POST, &_region, &mut _headers, &_query_params, Some(&_config.bucket_name), None, Some(&_body)).await?;
+        let _result = match _result.bytes().await {
+            Ok(value) => value,
+            Err(error) => return Err(GatewayError::Upstream(error.to_string()))
+        };
+        //  minio `remove_objects_api` requires `DeleteMarker` on each deleted key, S3 only sends it for versioned buckets
+        let mut _root = match Element::parse(_result.reader()) {
+            Ok(value) => value,
+            Err(error) => return Err(GatewayError::Upstream(error.to_string()))
+        };
+        while let Some(_deleted) = _root.take_child("Deleted") {
+            _response.deleted.push(get_default_text(&_deleted, "Key"));
+        }
+        while let Some(_error) = _root.take_child("Error") {
+            let _failure = DeleteFailure {
+                file_name: get_default_text(&_error, "Key"),
+                code: get_default_text(&_error, "Code"),
+                message: get_default_text(&_error, "Message"),
+            };
+            log::warn!("Error deleting `{}`: {}", _failure.file_name, _failure.message);
+            _response.errors.push(_failure);
+        }
+    }
+    Ok(_response)
+}
+
+/// Objects of a folder of all the clients `<client>/<folder>/`, like the trash, the top level of the bucket has a folder by client
+pub async fn list_client_folders(_client: &Client, _config: &GatewayConfig, _folder: &str) -> Result<Vec<Resource>, GatewayError> {
+    let _clients = list_objects_by_prefix(_client, _config, None, ListObjectsOptions {
+        fetch_all: true,
+        recursive: false,
+        ..ListObjectsOptions::default()
+    }).await?;
+    let mut _resources: Vec<Resource> = Vec::new();
+    for _client_folder in _clients.resources.unwrap_or_default().iter().filter(|_resource| _resource.is_prefix) {
+        let _objects = list_objects_by_prefix(_client, _config, Some(format!("{}{}/", _client_folder.name, _folder)), ListObjectsOptions {
+            fetch_all: true,
+            ..ListObjectsOptions::default()
+        }).await?;
+        _resources.extend(_objects.resources.unwrap_or_default());
+    }
+    Ok(_resources)
+}
+
+/// Delete all the objects of a container, like the attachments of a record, they are moved to trash when it is not permanent
+#[allow(clippy::too_many_arguments)]
+pub async fn delete_container_objects(_client: &Client, _config: &GatewayConfig, _client_id: Option<String>, _container_id: Option<String>, _container_type: Option<String>, _table_name: Option<String>, _column_name: Option<String>, _record_id: Option<String>, _user_id: Option<String>, _role_id: Option<String>, _is_permanent: bool, _deleted_by: Option<String>) -> Result<DeleteObjectsResponse, GatewayError> {
+    //  The separator avoids to match other containers starting with the same name, like record `10` and `100`
+    let _prefix = get_valid_path(_client_id, _container_id, _container_type, _table_name, _column_name, _record_id, _user_id, _role_id, true)? + "/";
+    let _objects = list_objects_by_prefix(_client, _config, Some(_prefix.to_owned()), ListObjectsOptions {
+        fetch_all: true,
+        ..ListObjectsOptions::default()
+    }).await?;
+    let _file_names = _objects.resources.unwrap_or_default()
+        .into_iter()
+        .map(|_resource| _resource.name)
+        .collect::<Vec<String>>();
+    let mut _response = match _is_permanent {
+        true => delete_objects(_client, _config, _file_names).await?,
+        false => move_objects_to_trash(_client, _config, _file_names, _deleted_by).await,
     };
-    let static_provider: StaticProvider = StaticProvider::new(
-        &_api_key, 
-        &_secret_key, None);
+    _response.parent_folder = Some(_prefix);
+    Ok(_response)
+}
 
-    let client = Client::new(_base_url.clone(), Some(Box::new(static_provider)), _cert_file_path, None).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let args_to_match = RemoveObjectArgs::new(
-        &_bucket_name,
-        _file_name.as_str()
-    );
+    fn get_file_name(_file_name: &str) -> Result<String, GatewayError> {
+        get_valid_file_name(Some("11".to_owned()), None, Some(_file_name.to_owned()), Some("attachment".to_owned()), Some("C_Order".to_owned()), None, Some("1".to_owned()), None, None)
+    }
 
-    match args_to_match {
-        Ok(value) => {
-            match client.remove_object(&value).await {
-                Ok(_) => Ok(()),
-                Err(error) => return Err(Error::new(ErrorKind::InvalidData.into(), error))
-            }
-        },
-        Err(error) => return Err(Error::new(ErrorKind::InvalidData.into(), error))
+    #[test]
+    fn rename_adds_the_suffix_before_the_extension() {
+        let _candidates = get_rename_candidates("11/client/attachment/c_order/1/invoice.pdf");
+        assert_eq!(_candidates[0], "11/client/attachment/c_order/1/invoice_1.pdf");
+        assert_eq!(_candidates[1], "11/client/attachment/c_order/1/invoice_2.pdf");
+        //  Only the last extension is kept after the suffix
+        assert_eq!(get_rename_candidates("1/client/a.tar.gz")[0], "1/client/a.tar_1.gz");
+    }
+
+    #[test]
+    fn rename_without_extension() {
+        assert_eq!(get_rename_candidates("1/client/README")[0], "1/client/README_1");
+        //  The dot of a folder is not an extension
+        assert_eq!(get_rename_candidates("1/client/v1.2/README")[0], "1/client/v1.2/README_1");
+        //  Hidden files have no extension
+        assert_eq!(get_rename_candidates("1/client/.env")[0], "1/client/.env_1");
+        assert_eq!(get_rename_candidates("1/client/.config.json")[0], "1/client/.config_1.json");
+    }
+
+    #[test]
+    fn rename_stops_after_the_attempt_limit() {
+        let _candidates = get_rename_candidates("1/client/invoice.pdf");
+        assert_eq!(_candidates.len(), usize::from(MAX_RENAME_ATTEMPTS));
+        assert_eq!(_candidates.last().unwrap(), "1/client/invoice_100.pdf");
+    }
+
+    #[test]
+    fn file_name_is_a_single_segment() {
+        assert_eq!(get_file_name("Invoice-12_v2.PDF").unwrap(), "11/client/attachment/c_order/1/invoice-12_v2.pdf");
+        assert_eq!(get_file_name("a/../../x").unwrap(), "11/client/attachment/c_order/1/a_.._.._x");
+        assert_eq!(get_file_name("a:b;c<d>e?f@g[h\\i]j^k").unwrap(), "11/client/attachment/c_order/1/a_b_c_d_e_f_g_h_i_j_k");
+        for _file_name in ["a/../../x", "../x", "a\\b"] {
+            let _key = get_file_name(_file_name).unwrap();
+            assert_eq!(get_resource_path(&_key).unwrap().container_type, "attachment");
+            assert_eq!(_key.matches('/').count(), 5);
+        }
+    }
+
+    #[test]
+    fn file_name_can_not_be_a_relative_segment() {
+        for _file_name in ["", ".", ".."] {
+            assert!(matches!(get_file_name(_file_name), Err(GatewayError::Validation(_))), "{:?}", _file_name);
+        }
+        assert_eq!(get_file_name("...").unwrap(), "11/client/attachment/c_order/1/...");
+    }
+
+    #[test]
+    fn resource_path_of_container_keys() {
+        assert_eq!(get_resource_path("11/client/attachment/c_order/1/file.txt").unwrap(), ResourcePath {
+            client_id: "11".to_string(),
+            user_id: None,
+            role_id: None,
+            container_type: "attachment".to_string(),
+        });
+        assert_eq!(get_resource_path("11/user/100/attachment/c_order/1/file.txt").unwrap(), ResourcePath {
+            client_id: "11".to_string(),
+            user_id: Some("100".to_string()),
+            role_id: None,
+            container_type: "attachment".to_string(),
+        });
+        assert_eq!(get_resource_path("11/role/102/resource/1/file.txt").unwrap(), ResourcePath {
+            client_id: "11".to_string(),
+            user_id: None,
+            role_id: Some("102".to_string()),
+            container_type: "resource".to_string(),
+        });
+    }
+
+    #[test]
+    fn resource_path_rejects_gateway_folders() {
+        assert!(get_resource_path("11/trash/1700000000000/client/attachment/c_order/1/file.txt").is_err());
+        assert!(get_resource_path("11/quarantine/1700000000000/client/attachment/c_order/1/file.txt").is_err());
+        assert!(get_resource_path("11/thumbnails/client/attachment/c_order/1/file.png/etag/256x256.png").is_err());
+        assert!(get_resource_path("11/pending/client/attachment/c_order/1/file.txt").is_err());
+        assert!(get_resource_path("11/user/100").is_err());
+    }
+
+    #[test]
+    fn resource_path_rejects_invalid_segments() {
+        assert!(get_resource_path("11/client/attachment/../../12/client/attachment/file.txt").is_err());
+        assert!(get_resource_path("11/client/./attachment/file.txt").is_err());
+        assert!(get_resource_path("11//client/attachment/file.txt").is_err());
+        assert!(get_resource_path("11/client/attachment/").is_err());
+        assert!(get_resource_path("").is_err());
+    }
+
+    #[test]
+    fn client_folder_file_name_keeps_the_key_without_client() {
+        assert_eq!(get_client_folder_file_name("11/client/attachment/c_order/1/file.txt", "trash/1700000000000").unwrap(), "11/trash/1700000000000/client/attachment/c_order/1/file.txt");
+        assert!(get_client_folder_file_name("11", "trash").is_err());
+        assert!(get_client_folder_file_name("/client/attachment/file.txt", "trash").is_err());
+        assert!(get_client_folder_file_name("11/", "trash").is_err());
     }
 }
@@ -0,0 +1,181 @@
+use std::path::Path;
+
+use bytes::{Bytes, BytesMut};
+use futures_util::{Stream, StreamExt};
+
+use crate::error::GatewayError;
+
+/// Bytes read from the start of an upload to detect its type
+const SNIFF_LENGTH: usize = 8192;
+/// Generic containers of other formats, like the Office documents in ZIP or OLE files
+const CONTAINER_CONTENT_TYPES: [&str; 2] = ["application/zip", "application/x-ole-storage"];
+
+/// Read the start of a body to detect its type from the magic bytes, the returned stream still has the whole body
+pub async fn sniff_stream<S>(_stream: S) -> Result<(Option<infer::Type>, impl Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync + 'static), GatewayError>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync + 'static,
+{
+    let mut _stream = Box::pin(_stream);
+    let mut _buffer = BytesMut::new();
+    while _buffer.len() < SNIFF_LENGTH {
+        match _stream.next().await {
+            Some(Ok(_chunk)) => _buffer.extend_from_slice(&_chunk),
+            Some(Err(error)) => {
+                log::error!("Error reading upload body: {}", error);
+                return Err(GatewayError::Validation(format!("Error reading the body: {}", error)))
+            },
+            None => break,
+        }
+    }
+    let _detected_type = infer::get(&_buffer);
+    let _head = futures_util::stream::iter(vec![Ok(_buffer.freeze())]);
+    Ok((_detected_type, _head.chain(_stream)))
+}
+
+/// The extension of the file does not match the detected type, like an executable renamed to `.png`
+pub fn is_content_type_mismatch(_file_name: &str, _detected_type: &infer::Type) -> bool {
+    let _detected_content_type = _detected_type.mime_type();
+    if CONTAINER_CONTENT_TYPES.contains(&_detected_content_type) {
+        return false
+    }
+    let _expected_content_types = mime_guess::from_path(Path::new(_file_name));
+    //  Files without a known extension or with a binary extension (like `.bin`) can have any content
+    if _expected_content_types.iter().all(|_expected| _expected.eq(&mime_guess::mime::APPLICATION_OCTET_STREAM)) {
+        return false
+    }
+    //  Types with aliases, like `audio/x-wav` and `audio/wav`, are compared by extension too
+    let _detected_content_types = mime_guess::from_ext(_detected_type.extension());
+    !_expected_content_types.iter().any(|_expected| {
+        _expected.essence_str().eq_ignore_ascii_case(_detected_content_type) || _detected_content_types.iter().any(|_detected| _detected.eq(&_expected))
+    })
+}
+
+/// Type stored for an upload, the detected type wins. Without magic bytes the declared type is only kept
+/// when it is a type of the extension, so a `.png` can not be stored as `text/html`
+pub fn get_upload_content_type(_file_name: &str, _detected_type: Option<&infer::Type>, _content_type: Option<String>, _reject_mismatch: bool) -> Result<String, GatewayError> {
+    if let Some(_detected_type) = _detected_type {
+        if _reject_mismatch && is_content_type_mismatch(_file_name, _detected_type) {
+            log::error!("Content of `{}` is `{}`, it does not match the extension", _file_name, _detected_type.mime_type());
+            return Err(GatewayError::UnsupportedMediaType(format!("The content `{}` does not match the extension of `{}`", _detected_type.mime_type(), _file_name)))
+        }
+        return Ok(_detected_type.mime_type().to_owned())
+    }
+    let _expected_content_types = mime_guess::from_path(Path::new(_file_name));
+    let _expected_content_type = match _expected_content_types.first() {
+        Some(value) if value.ne(&mime_guess::mime::APPLICATION_OCTET_STREAM) => value,
+        //  Without a known extension the declared type is the only one
+        _ => return Ok(_content_type.unwrap_or_else(|| mime_guess::mime::APPLICATION_OCTET_STREAM.to_string())),
+    };
+    let _content_type = match _content_type {
+        Some(value) => value,
+        None => return Ok(_expected_content_type.to_string()),
+    };
+    let _essence = _content_type.split(';').next().unwrap_or_default().trim();
+    if _expected_content_types.iter().any(|_expected| _expected.essence_str().eq_ignore_ascii_case(_essence)) {
+        return Ok(_content_type)
+    }
+    if _reject_mismatch {
+        log::error!("Content Type `{}` does not match the extension of `{}`", _content_type, _file_name);
+        return Err(GatewayError::UnsupportedMediaType(format!("The content type `{}` does not match the extension of `{}`", _content_type, _file_name)))
+    }
+    log::warn!("Content Type `{}` of `{}` replaced by `{}`", _content_type, _file_name, _expected_content_type);
+    Ok(_expected_content_type.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Start of a Windows executable
+    const EXE_HEADER: &[u8] = b"MZ\x90\x00\x03\x00\x00\x00\x04\x00\x00\x00\xff\xff\x00\x00";
+
+    #[test]
+    fn stores_the_detected_type() {
+        let _detected_type = infer::get(EXE_HEADER).unwrap();
+        assert_eq!(get_upload_content_type("setup.exe", Some(&_detected_type), Some("image/png".to_owned()), true).unwrap(), _detected_type.mime_type());
+        assert_eq!(get_upload_content_type("avatar.png", Some(&_detected_type), Some("image/png".to_owned()), false).unwrap(), _detected_type.mime_type());
+        assert!(matches!(get_upload_content_type("avatar.png", Some(&_detected_type), None, true), Err(GatewayError::UnsupportedMediaType(_))));
+    }
+
+    #[test]
+    fn keeps_the_declared_type_of_the_extension() {
+        assert_eq!(get_upload_content_type("notes.txt", None, Some("text/plain; charset=utf-8".to_owned()), true).unwrap(), "text/plain; charset=utf-8");
+        assert_eq!(get_upload_content_type("data.csv", None, Some("TEXT/CSV".to_owned()), true).unwrap(), "TEXT/CSV");
+        assert_eq!(get_upload_content_type("notes.txt", None, None, true).unwrap(), "text/plain");
+    }
+
+    #[test]
+    fn replaces_or_rejects_a_declared_type_of_other_extension() {
+        assert_eq!(get_upload_content_type("avatar.png", None, Some("text/html".to_owned()), false).unwrap(), "image/png");
+        assert_eq!(get_upload_content_type("avatar.png", None, Some("image/svg+xml".to_owned()), false).unwrap(), "image/png");
+        assert!(matches!(get_upload_content_type("avatar.png", None, Some("text/html".to_owned()), true), Err(GatewayError::UnsupportedMediaType(_))));
+    }
+
+    #[test]
+    fn keeps_the_declared_type_without_known_extension() {
+        assert_eq!(get_upload_content_type("README", None, Some("text/markdown".to_owned()), true).unwrap(), "text/markdown");
+        assert_eq!(get_upload_content_type("dump.bin", None, Some("application/x-custom".to_owned()), true).unwrap(), "application/x-custom");
+        assert_eq!(get_upload_content_type("README", None, None, true).unwrap(), "application/octet-stream");
+    }
+
+    const PNG_HEADER: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+    const ZIP_HEADER: &[u8] = b"PK\x03\x04\x14\x00\x00\x00\x08\x00";
+
+    async fn sniff_chunks(_chunks: Vec<Vec<u8>>) -> (Option<infer::Type>, Vec<u8>) {
+        let _stream = futures_util::stream::iter(_chunks.into_iter().map(|_chunk| Ok(Bytes::from(_chunk))));
+        let (_detected_type, _stream) = sniff_stream(_stream).await.unwrap();
+        let _body = _stream.map(|_chunk| _chunk.unwrap().to_vec()).concat().await;
+        (_detected_type, _body)
+    }
+
+    #[test]
+    fn rejects_an_executable_renamed_to_png() {
+        let _detected_type = infer::get(EXE_HEADER).unwrap();
+        assert!(is_content_type_mismatch("avatar.png", &_detected_type));
+        assert!(!is_content_type_mismatch("setup.exe", &_detected_type));
+    }
+
+    #[test]
+    fn accepts_office_documents_sniffed_as_zip() {
+        let _detected_type = infer::get(ZIP_HEADER).unwrap();
+        assert_eq!(_detected_type.mime_type(), "application/zip");
+        assert!(!is_content_type_mismatch("report.docx", &_detected_type));
+        assert!(!is_content_type_mismatch("report.xlsx", &_detected_type));
+    }
+
+    #[test]
+    fn accepts_any_content_with_unknown_extension() {
+        let _detected_type = infer::get(PNG_HEADER).unwrap();
+        assert!(!is_content_type_mismatch("photo.unknown-extension", &_detected_type));
+        assert!(!is_content_type_mismatch("photo", &_detected_type));
+        assert!(!is_content_type_mismatch("photo.bin", &_detected_type));
+        assert!(!is_content_type_mismatch("photo.png", &_detected_type));
+    }
+
+    #[tokio::test]
+    async fn sniffs_across_short_chunks() {
+        //  The first chunk is shorter than the magic bytes, the window is filled with the next ones
+        let mut _chunks = vec![PNG_HEADER[..2].to_vec(), PNG_HEADER[2..].to_vec()];
+        _chunks.extend((0..3).map(|_index| vec![_index; SNIFF_LENGTH]));
+        let _expected = _chunks.concat();
+        let (_detected_type, _body) = sniff_chunks(_chunks).await;
+        assert_eq!(_detected_type.unwrap().mime_type(), "image/png");
+        assert_eq!(_body, _expected);
+    }
+
+    #[tokio::test]
+    async fn sniffs_a_stream_that_ends_early() {
+        let (_detected_type, _body) = sniff_chunks(vec![b"plain".to_vec(), b" text".to_vec()]).await;
+        assert!(_detected_type.is_none());
+        assert_eq!(_body, b"plain text");
+        let (_detected_type, _body) = sniff_chunks(Vec::new()).await;
+        assert!(_detected_type.is_none());
+        assert!(_body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn fails_on_body_errors() {
+        let _stream = futures_util::stream::iter(vec![Ok(Bytes::from_static(b"start")), Err(std::io::Error::other("reset"))]);
+        assert!(matches!(sniff_stream(_stream).await, Err(GatewayError::Validation(_))));
+    }
+}
@@ -0,0 +1,237 @@
+use std::io::Cursor;
+use std::path::Path;
+
+use bytes::Bytes;
+use image::{DynamicImage, ImageFormat};
+use minio::s3::client::Client;
+use minio::s3::response::StatObjectResponse;
+
+use crate::config::GatewayConfig;
+use crate::controller::metadata::get_display_name;
+use crate::controller::s3::{delete_object_keys, get_client_folder_file_name, get_object_stat, get_object_stream, get_resource_path, list_objects_by_prefix, put_object_data, ListObjectsOptions};
+use crate::error::GatewayError;
+
+/// Folder of each client where the generated thumbnails are cached
+pub const THUMBNAIL_FOLDER: &str = "thumbnails";
+/// Size of the box when neither `width` nor `height` are sent
+const DEFAULT_THUMBNAIL_SIZE: u32 = 256;
+const MAX_THUMBNAIL_SIZE: u32 = 2048;
+/// Images larger than it are not loaded in memory to be resized
+const MAX_SOURCE_SIZE: usize = 50 * 1024 * 1024;
+const SUPPORTED_FORMATS: [ImageFormat; 4] = [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::WebP, ImageFormat::Gif];
+
+pub enum ThumbnailContent {
+    /// Variant cached in S3 by a previous request
+    Cached(reqwest::Response),
+    /// Variant generated on this request
+    Generated(Bytes),
+}
+
+pub struct Thumbnail {
+    pub file_name: String,
+    /// Name of the source with the extension of the thumbnail format
+    pub display_name: String,
+    pub content_type: String,
+    pub content: ThumbnailContent,
+}
+
+/// Box where the thumbnail fits, without size it is a square of 256 pixels
+fn get_thumbnail_size(_width: Option<u32>, _height: Option<u32>) -> Result<(Option<u32>, Option<u32>), GatewayError> {
+    for _size in [_width, _height].into_iter().flatten() {
+        if _size == 0 || _size > MAX_THUMBNAIL_SIZE {
+            log::error!("Invalid Thumbnail Size {}", _size);
+            return Err(GatewayError::Validation(format!("Width and height must be between 1 and {}", MAX_THUMBNAIL_SIZE)))
+        }
+    }
+    match (_width, _height) {
+        (None, None) => Ok((Some(DEFAULT_THUMBNAIL_SIZE), Some(DEFAULT_THUMBNAIL_SIZE))),
+        _ => Ok((_width, _height)),
+    }
+}
+
+/// Format stored on upload or guessed from the extension
+fn get_source_format(_stat: &StatObjectResponse) -> Result<ImageFormat, GatewayError> {
+    let _format = _stat.headers.get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| ImageFormat::from_mime_type(value.split(';').next().unwrap_or(value).trim()))
+        .or_else(|| ImageFormat::from_path(Path::new(&_stat.object_name)).ok());
+    match _format {
+        Some(_format) if SUPPORTED_FORMATS.contains(&_format) => Ok(_format),
+        _ => {
+            log::error!("`{}` is not a supported image", _stat.object_name);
+            Err(GatewayError::UnsupportedMediaType(format!("Thumbnails are only available for JPEG, PNG, WebP and GIF images, `{}` is not supported", _stat.object_name)))
+        }
+    }
+}
+
+/// `jpeg`, `png`, `webp` or `gif`, the format of the source when it is not sent
+fn get_thumbnail_format(_format: Option<String>, _source_format: ImageFormat) -> Result<ImageFormat, GatewayError> {
+    match _format {
+        Some(_value) => match ImageFormat::from_extension(_value.to_lowercase()) {
+            Some(_format) if SUPPORTED_FORMATS.contains(&_format) => Ok(_format),
+            _ => {
+                log::error!("Invalid Thumbnail Format {:?}", _value);
+                Err(GatewayError::Validation(format!("Invalid format `{}`, allowed values are `jpeg`, `png`, `webp` or `gif`", _value)))
+            }
+        },
+        None => Ok(_source_format),
+    }
+}
+
+/// Folder of the thumbnails of an object `<client>/thumbnails/<key without client>/`
+pub fn get_thumbnail_folder(_file_name: &str) -> Result<String, GatewayError> {
+    Ok(get_client_folder_file_name(_file_name, THUMBNAIL_FOLDER)? + "/")
+}
+
+/// Decode the image and fit it inside the box keeping the aspect ratio, the small images are not enlarged
+fn resize_image(_data: &[u8], _width: Option<u32>, _height: Option<u32>, _format: ImageFormat) -> Result<Vec<u8>, GatewayError> {
+    //  The content is trusted over the stored type, only the supported decoders are compiled
+    let _image = image::load_from_memory(_data).map_err(|error| {
+        log::error!("Error decoding image: {}", error);
+        GatewayError::UnsupportedMediaType(format!("The image can not be decoded: {}", error))
+    })?;
+    let _width = _width.unwrap_or(u32::MAX).min(_image.width());
+    let _height = _height.unwrap_or(u32::MAX).min(_image.height());
+    let _image = _image.thumbnail(_width, _height);
+    //  JPEG has no alpha channel, the other encoders receive 8 bits RGBA
+    let _image = match _format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(_image.to_rgb8()),
+        _ => DynamicImage::ImageRgba8(_image.to_rgba8()),
+    };
+    let mut _buffer = Cursor::new(Vec::new());
+    _image.write_to(&mut _buffer, _format).map_err(|error| {
+        log::error!("Error encoding thumbnail: {}", error);
+        GatewayError::Validation(format!("The thumbnail can not be encoded as {:?}: {}", _format, error))
+    })?;
+    Ok(_buffer.into_inner())
+}
+
+async fn list_thumbnails(_client: &Client, _config: &GatewayConfig, _thumbnail_folder: &str) -> Result<Vec<String>, GatewayError> {
+    let _thumbnails = list_objects_by_prefix(_client, _config, Some(_thumbnail_folder.to_owned()), ListObjectsOptions {
+        fetch_all: true,
+        ..ListObjectsOptions::default()
+    }).await?;
+    Ok(_thumbnails.resources.unwrap_or_default()
+        .into_iter()
+        .map(|_thumbnail| _thumbnail.name)
+        .collect())
+}
+
+/// Remove the cached thumbnails of previous versions of the object, their ETag folder is not the current one
+async fn remove_outdated_thumbnails(_client: &Client, _config: &GatewayConfig, _thumbnail_folder: &str, _current_folder: &str) -> Result<usize, GatewayError> {
+    let _outdated_file_names = list_thumbnails(_client, _config, _thumbnail_folder).await?
+        .into_iter()
+        .filter(|_thumbnail| !_thumbnail.starts_with(_current_folder))
+        .collect::<Vec<String>>();
+    if _outdated_file_names.is_empty() {
+        return Ok(0)
+    }
+    let _response = delete_object_keys(_client, _config, _outdated_file_names).await?;
+    Ok(_response.deleted.len())
+}
+
+/// Only the objects of a container have thumbnails, the keys of the gateway folders are skipped
+fn has_thumbnails(_file_name: &str) -> bool {
+    matches!(_file_name.split('/').nth(1), Some("client" | "user" | "role"))
+}
+
+/// Remove the cached thumbnails of deleted or moved objects, a failure is logged and does not fail the delete
+pub async fn remove_thumbnails(_client: &Client, _config: &GatewayConfig, _file_names: &[String]) {
+    let mut _thumbnail_file_names = Vec::new();
+    for _file_name in _file_names.iter().filter(|_file_name| has_thumbnails(_file_name)) {
+        let _thumbnail_folder = match get_thumbnail_folder(_file_name) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        match list_thumbnails(_client, _config, &_thumbnail_folder).await {
+            Ok(_thumbnails) => _thumbnail_file_names.extend(_thumbnails),
+            Err(error) => log::warn!("Error listing thumbnails of `{}`: {}", _file_name, error),
+        }
+    }
+    if _thumbnail_file_names.is_empty() {
+        return
+    }
+    match delete_object_keys(_client, _config, _thumbnail_file_names).await {
+        Ok(_response) => log::info!("{} thumbnails of deleted objects removed", _response.deleted.len()),
+        Err(error) => log::warn!("Error removing thumbnails of deleted objects: {}", error),
+    }
+}
+
+/// Thumbnail of an image, the variants are cached in `<client>/thumbnails/<key without client>/<etag>/<width>x<height>.<format>`
+/// so a new version of the image (other ETag) generates them again
+pub async fn get_thumbnail(_client: &Client, _http_client: &reqwest::Client, _config: &GatewayConfig, _file_name: String, _width: Option<u32>, _height: Option<u32>, _format: Option<String>) -> Result<Thumbnail, GatewayError> {
+    get_resource_path(&_file_name)?;
+    let (_width, _height) = get_thumbnail_size(_width, _height)?;
+    let _stat = get_object_stat(_client, _config, _file_name.to_owned(), None).await?;
+    let _format = get_thumbnail_format(_format, get_source_format(&_stat)?)?;
+    let _extension = _format.extensions_str().first().copied().unwrap_or("img");
+    let _thumbnail_folder = get_thumbnail_folder(&_file_name)?;
+    let _current_folder = format!("{}{}/", _thumbnail_folder, _stat.etag.trim_matches('"'));
+    let _thumbnail_file_name = format!("{}{}x{}.{}", _current_folder, _width.unwrap_or(0), _height.unwrap_or(0), _extension);
+    let _display_name = Path::new(&get_display_name(&_file_name, Some(&_stat.user_metadata))).with_extension(_extension).to_string_lossy().into_owned();
+    let _content_type = _format.to_mime_type().to_owned();
+    match get_object_stream(_client, _http_client, _config, _thumbnail_file_name.to_owned(), None, Vec::new()).await {
+        Ok(_response) => return Ok(Thumbnail {
+            file_name: _thumbnail_file_name,
+            display_name: _display_name,
+            content_type: _content_type,
+            content: ThumbnailContent::Cached(_response),
+        }),
+        Err(GatewayError::NotFound(_)) => {},
+        Err(error) => return Err(error),
+    }
+    if _stat.size > MAX_SOURCE_SIZE {
+        log::error!("Thumbnail of `{}` can not be generated, size {} exceeds {}", _file_name, _stat.size, MAX_SOURCE_SIZE);
+        return Err(GatewayError::Validation(format!("Thumbnails are only available for images up to {} bytes", MAX_SOURCE_SIZE)))
+    }
+    let _source = get_object_stream(_client, _http_client, _config, _file_name.to_owned(), None, Vec::new()).await?
+        .bytes()
+        .await
+        .map_err(|error| {
+            log::error!("Error downloading `{}`: {}", _file_name, error);
+            GatewayError::Upstream(format!("Error downloading `{}`: {}", _file_name, error))
+        })?;
+    //  Decode and resize are CPU bound, they must not block the request workers
+    let _data: Bytes = tokio::task::spawn_blocking(move || resize_image(&_source, _width, _height, _format))
+        .await
+        .map_err(|error| {
+            log::error!("Error resizing `{}`: {}", _file_name, error);
+            GatewayError::Upstream(format!("Error resizing `{}`", _file_name))
+        })??
+        .into();
+    //  The thumbnail is answered even if it can not be cached, the clone of `Bytes` shares the buffer
+    match put_object_data(_client, _http_client, _config, _thumbnail_file_name.to_owned(), &_content_type, _data.clone()).await {
+        Ok(_) => match remove_outdated_thumbnails(_client, _config, &_thumbnail_folder, &_current_folder).await {
+            Ok(_removed) if _removed > 0 => log::info!("{} outdated thumbnails of `{}` removed", _removed, _file_name),
+            Ok(_) => {},
+            Err(error) => log::warn!("Error removing outdated thumbnails of `{}`: {}", _file_name, error),
+        },
+        Err(error) => log::warn!("Thumbnail `{}` was not cached: {}", _thumbnail_file_name, error),
+    }
+    Ok(Thumbnail {
+        file_name: _thumbnail_file_name,
+        display_name: _display_name,
+        content_type: _content_type,
+        content: ThumbnailContent::Generated(_data),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_container_objects_have_thumbnails() {
+        assert!(has_thumbnails("11/client/attachment/c_order/1/photo.jpg"));
+        assert!(has_thumbnails("11/user/100/attachment/c_order/1/photo.jpg"));
+        assert!(has_thumbnails("11/role/102/attachment/c_order/1/photo.jpg"));
+        assert!(!has_thumbnails("11/thumbnails/client/attachment/c_order/1/photo.jpg/abc/256x256.jpg"));
+        assert!(!has_thumbnails("11/trash/1700000000000/client/attachment/c_order/1/photo.jpg"));
+        assert!(!has_thumbnails("11/pending/client/attachment/c_order/1/photo.jpg"));
+    }
+
+    #[test]
+    fn builds_thumbnail_folder() {
+        assert_eq!(get_thumbnail_folder("11/client/attachment/c_order/1/photo.jpg").unwrap(), "11/thumbnails/client/attachment/c_order/1/photo.jpg/");
+    }
+}
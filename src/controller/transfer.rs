@@ -0,0 +1,180 @@
+use minio::s3::client::Client;
+use serde::Serialize;
+
+use crate::config::{CollisionPolicy, GatewayConfig};
+use crate::controller::metadata::{get_replace_headers, COLUMN_NAME_KEY, CONTAINER_TYPE_KEY, RECORD_ID_KEY, TABLE_NAME_KEY};
+use crate::controller::s3::{copy_object, delete_objects, get_object_stat, get_object_upload_policy, get_resource_path, get_valid_path, list_objects_by_prefix, resolve_file_name_collision, ListObjectsOptions, Resource};
+use crate::error::GatewayError;
+
+/// Container where the objects are copied or moved, the same parameters of the upload
+#[derive(Debug, Clone, Default)]
+pub struct TransferDestination {
+    pub client_id: Option<String>,
+    pub container_id: Option<String>,
+    pub container_type: Option<String>,
+    pub table_name: Option<String>,
+    pub column_name: Option<String>,
+    pub record_id: Option<String>,
+    pub user_id: Option<String>,
+    pub role_id: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TransferFailure {
+    pub file_name: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TransferResponse {
+    pub parent_folder: String,
+    pub resources: Vec<Resource>,
+    pub errors: Vec<TransferFailure>,
+}
+
+/// Prefix of the objects of a source folder, the destination folder can not be inside of it
+fn get_source_prefix(_file_name: &str, _parent_folder: &str) -> Result<String, GatewayError> {
+    let _prefix = format!("{}/", _file_name.trim_end_matches('/'));
+    if _parent_folder.starts_with(&_prefix) {
+        log::error!("`{}` can not be moved inside itself `{}`", _prefix, _parent_folder);
+        return Err(GatewayError::Validation("The destination can not be inside of the source folder".to_string()))
+    }
+    Ok(_prefix)
+}
+
+/// Key in the destination folder, the objects of a source folder keep their path under it and a single object keeps its name
+fn get_target_file_name(_parent_folder: &str, _source_prefix: Option<&str>, _source_file_name: &str) -> Result<String, GatewayError> {
+    let _path = match _source_prefix.and_then(|_prefix| _source_file_name.strip_prefix(_prefix)) {
+        Some(_path) => _path,
+        None => _source_file_name.rsplit('/').next().unwrap_or(_source_file_name),
+    };
+    let _file_name = format!("{}{}", _parent_folder, _path.to_lowercase());
+    if _source_file_name.eq(&_file_name) {
+        log::error!("`{}` is copied to itself", _file_name);
+        return Err(GatewayError::Validation(format!("`{}` is already in the destination", _file_name)))
+    }
+    Ok(_file_name)
+}
+
+/// Copy an object or all the objects of a folder (`_is_recursive`) to other container, the source is removed when `_is_move`
+pub async fn transfer_objects(_client: &Client, _config: &GatewayConfig, _file_name: String, _destination: TransferDestination, _is_recursive: bool, _is_move: bool, _policy: CollisionPolicy) -> Result<TransferResponse, GatewayError> {
+    //  Only objects and folders inside a container, a whole client can not be moved
+    get_resource_path(_file_name.trim_end_matches('/'))?;
+    let _parent_folder = get_valid_path(_destination.client_id, _destination.container_id, _destination.container_type.to_owned(), _destination.table_name.to_owned(), _destination.column_name.to_owned(), _destination.record_id.to_owned(), _destination.user_id, _destination.role_id, true)? + "/";
+    let _source_prefix = match _is_recursive {
+        true => Some(get_source_prefix(&_file_name, &_parent_folder)?),
+        false => None,
+    };
+    let _sources = match &_source_prefix {
+        Some(_prefix) => {
+            let _objects = list_objects_by_prefix(_client, _config, Some(_prefix.to_owned()), ListObjectsOptions {
+                fetch_all: true,
+                ..ListObjectsOptions::default()
+            }).await?;
+            _objects.resources.unwrap_or_default()
+                .into_iter()
+                .map(|_resource| _resource.name)
+                .collect::<Vec<String>>()
+        },
+        None => vec![_file_name.to_owned()],
+    };
+    if _sources.is_empty() {
+        log::warn!("Folder `{}` has no objects", _file_name);
+        return Err(GatewayError::NotFound(format!("Folder `{}` does not exist", _file_name)))
+    }
+    let mut _response = TransferResponse {
+        parent_folder: _parent_folder.to_owned(),
+        ..TransferResponse::default()
+    };
+    let mut _copied_file_names: Vec<String> = Vec::new();
+    for _source_file_name in _sources {
+        let _result = match get_target_file_name(&_parent_folder, _source_prefix.as_deref(), &_source_file_name) {
+            Ok(_target_file_name) => copy_to_container(_client, _config, &_source_file_name, _target_file_name, &_destination.container_type, &_destination.table_name, &_destination.column_name, &_destination.record_id, _policy).await,
+            Err(error) => Err(error),
+        };
+        match _result {
+            Ok(_resource) => {
+                _copied_file_names.push(_source_file_name);
+                _response.resources.push(_resource);
+            },
+            //  A single object fails as a request, the folders report each object
+            Err(error) if !_is_recursive => return Err(error),
+            Err(error) => {
+                log::warn!("Error copying `{}`: {}", _source_file_name, error);
+                _response.errors.push(TransferFailure {
+                    file_name: _source_file_name,
+                    message: error.to_string(),
+                });
+            }
+        }
+    }
+    if _is_move && !_copied_file_names.is_empty() {
+        let _deleted = delete_objects(_client, _config, _copied_file_names).await?;
+        _response.errors.extend(_deleted.errors.into_iter().map(|_error| TransferFailure {
+            file_name: _error.file_name,
+            message: _error.message,
+        }));
+    }
+    Ok(_response)
+}
+
+/// Server side copy of a single object, the container metadata is replaced with the destination values
+#[allow(clippy::too_many_arguments)]
+async fn copy_to_container(_client: &Client, _config: &GatewayConfig, _source_file_name: &str, _file_name: String, _container_type: &Option<String>, _table_name: &Option<String>, _column_name: &Option<String>, _record_id: &Option<String>, _policy: CollisionPolicy) -> Result<Resource, GatewayError> {
+    let _stat = get_object_stat(_client, _config, _source_file_name.to_owned(), None).await?;
+    //  The destination container can have a stricter upload policy than the source
+    if let Some(_upload_policy) = get_object_upload_policy(_config, &_file_name) {
+        let _content_type = _stat.headers.get("content-type").and_then(|value| value.to_str().ok());
+        _upload_policy.validate(_content_type, Some(_stat.size))?;
+    }
+    let _file_name = resolve_file_name_collision(_client, _config, _file_name, _policy).await?;
+    let mut _user_metadata = _stat.user_metadata.to_owned();
+    for (_key, _value) in [(CONTAINER_TYPE_KEY, _container_type), (TABLE_NAME_KEY, _table_name), (COLUMN_NAME_KEY, _column_name), (RECORD_ID_KEY, _record_id)] {
+        match _value {
+            Some(_value) => _user_metadata.insert(_key.to_owned(), _value.to_owned()),
+            None => _user_metadata.remove(_key),
+        };
+    }
+    let _headers = get_replace_headers(&_stat, _user_metadata);
+    copy_object(_client, _config, &_stat, &_file_name, Some(_headers)).await?;
+    let _stat = get_object_stat(_client, _config, _file_name, None).await?;
+    Ok(Resource::from_stat(&_stat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE_FOLDER: &str = "11/client/attachment/c_order/1/";
+    const DESTINATION_FOLDER: &str = "11/client/attachment/c_invoice/2/";
+
+    #[test]
+    fn single_object_keeps_its_name() {
+        assert_eq!(get_target_file_name(DESTINATION_FOLDER, None, "11/client/attachment/c_order/1/docs/Invoice.PDF").unwrap(), "11/client/attachment/c_invoice/2/invoice.pdf");
+    }
+
+    #[test]
+    fn folder_objects_keep_their_path() {
+        let _prefix = get_source_prefix("11/client/attachment/c_order/1", DESTINATION_FOLDER).unwrap();
+        assert_eq!(_prefix, SOURCE_FOLDER);
+        assert_eq!(get_source_prefix(SOURCE_FOLDER, DESTINATION_FOLDER).unwrap(), SOURCE_FOLDER);
+        assert_eq!(get_target_file_name(DESTINATION_FOLDER, Some(&_prefix), "11/client/attachment/c_order/1/docs/2024/Invoice.pdf").unwrap(), "11/client/attachment/c_invoice/2/docs/2024/invoice.pdf");
+        assert_eq!(get_target_file_name(DESTINATION_FOLDER, Some(&_prefix), "11/client/attachment/c_order/1/file.txt").unwrap(), "11/client/attachment/c_invoice/2/file.txt");
+    }
+
+    #[test]
+    fn rejects_destination_inside_of_source() {
+        for _parent_folder in [SOURCE_FOLDER, "11/client/attachment/c_order/1/docs/"] {
+            assert!(matches!(get_source_prefix("11/client/attachment/c_order/1", _parent_folder), Err(GatewayError::Validation(_))));
+        }
+        //  A sibling with the same start is not inside
+        assert!(get_source_prefix("11/client/attachment/c_order/1", "11/client/attachment/c_order/10/").is_ok());
+    }
+
+    #[test]
+    fn rejects_copy_to_itself() {
+        assert!(matches!(get_target_file_name(SOURCE_FOLDER, None, "11/client/attachment/c_order/1/file.txt"), Err(GatewayError::Validation(_))));
+        //  The key is stored in lowercase, an object with uppercase is not the same
+        assert!(get_target_file_name(SOURCE_FOLDER, None, "11/client/attachment/c_order/1/File.txt").is_ok());
+    }
+}
@@ -0,0 +1,169 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use minio::s3::client::Client;
+
+use crate::config::{CollisionPolicy, GatewayConfig};
+use crate::controller::metadata::{get_replace_headers, DELETED_AT_KEY, DELETED_BY_KEY, TRASH_ORIGIN_KEY};
+use crate::controller::s3::{copy_object, delete_object, delete_objects, get_client_folder_file_name, get_object_stat, get_valid_path_name, list_client_folders, list_objects_by_prefix, resolve_file_name_collision, DeleteFailure, DeleteObjectsResponse, ListObjectsOptions, Resource, ResourceResponse};
+use crate::error::GatewayError;
+
+/// Folder of each client where the deleted objects are kept
+pub const TRASH_FOLDER: &str = "trash";
+/// Time between runs of the purge job
+pub const TRASH_PURGE_INTERVAL_SECONDS: u64 = 3600;
+
+/// Object key of a trash item and the key it had before the delete
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashPath {
+    pub file_name: String,
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// Trash key `<client>/trash/<deleted at millis>/<key without client>`, the timestamp keeps apart the deletes of the same key
+pub fn get_trash_file_name(_file_name: &str, _deleted_at: DateTime<Utc>) -> Result<String, GatewayError> {
+    get_client_folder_file_name(_file_name, &format!("{}/{}", TRASH_FOLDER, _deleted_at.timestamp_millis()))
+}
+
+pub fn get_trash_path(_trash_file_name: &str) -> Result<TrashPath, GatewayError> {
+    let _segments = _trash_file_name.splitn(4, '/').collect::<Vec<&str>>();
+    if let [_client_id, _folder, _deleted_at, _path] = _segments.as_slice() {
+        let _deleted_at = _deleted_at.parse::<i64>().ok().and_then(|millis| Utc.timestamp_millis_opt(millis).single());
+        if let Some(_deleted_at) = _deleted_at {
+            if _folder.eq(&TRASH_FOLDER) && !_client_id.is_empty() && !_path.is_empty() {
+                return Ok(TrashPath {
+                    file_name: format!("{}/{}", _client_id, _path),
+                    deleted_at: _deleted_at,
+                })
+            }
+        }
+    }
+    log::warn!("Invalid Trash File Name {:?}", _trash_file_name);
+    Err(GatewayError::Validation(format!("`{}` is not a trash file", _trash_file_name)))
+}
+
+/// Soft delete, the object is moved to the trash of its client with the deletion data in metadata
+pub async fn move_to_trash(_client: &Client, _config: &GatewayConfig, _file_name: String, _deleted_by: Option<String>) -> Result<Resource, GatewayError> {
+    let _stat = get_object_stat(_client, _config, _file_name.to_owned(), None).await?;
+    let _deleted_at = Utc::now();
+    let _trash_file_name = get_trash_file_name(&_file_name, _deleted_at)?;
+    let mut _user_metadata = _stat.user_metadata.to_owned();
+    _user_metadata.insert(DELETED_AT_KEY.to_owned(), _deleted_at.to_rfc3339());
+    _user_metadata.insert(TRASH_ORIGIN_KEY.to_owned(), _file_name.to_owned());
+    if let Some(_deleted_by) = _deleted_by {
+        _user_metadata.insert(DELETED_BY_KEY.to_owned(), _deleted_by);
+    }
+    let _headers = get_replace_headers(&_stat, _user_metadata);
+    copy_object(_client, _config, &_stat, &_trash_file_name, Some(_headers)).await?;
+    delete_object(_client, _config, _file_name).await?;
+    let _stat = get_object_stat(_client, _config, _trash_file_name, None).await?;
+    Ok(Resource::from_stat(&_stat))
+}
+
+/// Soft delete of a list of objects, the objects that fail are reported instead of stop
+pub async fn move_objects_to_trash(_client: &Client, _config: &GatewayConfig, _file_names: Vec<String>, _deleted_by: Option<String>) -> DeleteObjectsResponse {
+    let mut _response = DeleteObjectsResponse::default();
+    for _file_name in _file_names {
+        match move_to_trash(_client, _config, _file_name.to_owned(), _deleted_by.to_owned()).await {
+            Ok(_) => _response.deleted.push(_file_name),
+            Err(error) => {
+                log::error!("Error moving `{}` to trash: {}", _file_name, error);
+                _response.errors.push(DeleteFailure {
+                    file_name: _file_name,
+                    code: error.code().to_owned(),
+                    message: error.message().to_owned(),
+                })
+            }
+        }
+    }
+    _response
+}
+
+/// Move a trash item back to its key, the collision policy is applied if the key was used again
+pub async fn restore_from_trash(_client: &Client, _config: &GatewayConfig, _trash_file_name: String, _policy: CollisionPolicy) -> Result<Resource, GatewayError> {
+    let _trash_path = get_trash_path(&_trash_file_name)?;
+    let _stat = get_object_stat(_client, _config, _trash_file_name.to_owned(), None).await?;
+    let _file_name = resolve_file_name_collision(_client, _config, _trash_path.file_name, _policy).await?;
+    let mut _user_metadata = _stat.user_metadata.to_owned();
+    _user_metadata.remove(DELETED_AT_KEY);
+    _user_metadata.remove(DELETED_BY_KEY);
+    _user_metadata.remove(TRASH_ORIGIN_KEY);
+    let _headers = get_replace_headers(&_stat, _user_metadata);
+    copy_object(_client, _config, &_stat, &_file_name, Some(_headers)).await?;
+    delete_object(_client, _config, _trash_file_name).await?;
+    let _stat = get_object_stat(_client, _config, _file_name, None).await?;
+    Ok(Resource::from_stat(&_stat))
+}
+
+pub async fn get_list_trash(_client: &Client, _config: &GatewayConfig, _client_id: Option<String>, _options: ListObjectsOptions) -> Result<ResourceResponse, GatewayError> {
+    let _client_id = match _client_id {
+        Some(value) => get_valid_path_name(value).to_lowercase(),
+        None => {
+            log::error!("Client ID is Mandatory");
+            return Err(GatewayError::Validation("Client ID is Mandatory".to_owned()))
+        }
+    };
+    let _prefix = format!("{}/{}/", _client_id, TRASH_FOLDER);
+    list_objects_by_prefix(_client, _config, Some(_prefix), ListObjectsOptions {
+        recursive: true,
+        .._options
+    }).await
+}
+
+/// Remove the trash items older than the retention of all the clients, returns the number of removed objects
+pub async fn purge_trash(_client: &Client, _config: &GatewayConfig) -> Result<usize, GatewayError> {
+    let _expiration = Utc::now() - Duration::days(_config.trash_retention_days.into());
+    let _expired_file_names = list_client_folders(_client, _config, TRASH_FOLDER).await?
+        .into_iter()
+        .filter(|_item| get_trash_path(&_item.name).is_ok_and(|_trash_path| _trash_path.deleted_at < _expiration))
+        .map(|_item| _item.name)
+        .collect::<Vec<String>>();
+    if _expired_file_names.is_empty() {
+        return Ok(0)
+    }
+    let _response = delete_objects(_client, _config, _expired_file_names).await?;
+    Ok(_response.deleted.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_deleted_at() -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(1700000000123).unwrap()
+    }
+
+    #[test]
+    fn trash_file_name_round_trip() {
+        for _file_name in ["11/client/attachment/c_order/1/file.txt", "11/user/100/attachment/c_order/1/file.txt", "11/role/102/window/100/file.txt"] {
+            let _trash_file_name = get_trash_file_name(_file_name, get_deleted_at()).unwrap();
+            assert_eq!(get_trash_path(&_trash_file_name).unwrap(), TrashPath {
+                file_name: _file_name.to_owned(),
+                deleted_at: get_deleted_at(),
+            });
+        }
+        assert_eq!(get_trash_file_name("11/client/attachment/c_order/1/file.txt", get_deleted_at()).unwrap(), "11/trash/1700000000123/client/attachment/c_order/1/file.txt");
+    }
+
+    #[test]
+    fn trash_path_with_trash_inside() {
+        //  Only the second segment is the trash folder
+        let _file_name = "11/client/attachment/trash/1/trash";
+        let _trash_file_name = get_trash_file_name(_file_name, get_deleted_at()).unwrap();
+        assert_eq!(_trash_file_name, "11/trash/1700000000123/client/attachment/trash/1/trash");
+        assert_eq!(get_trash_path(&_trash_file_name).unwrap().file_name, _file_name);
+    }
+
+    #[test]
+    fn rejects_keys_outside_of_trash() {
+        for _file_name in [
+            "11/client/attachment/c_order/1/file.txt",
+            "11/client/1700000000123/attachment/file.txt",
+            "11/trash/not-a-date/client/file.txt",
+            "11/trash/1700000000123",
+            "11/trash/1700000000123/",
+            "/trash/1700000000123/client/file.txt",
+            "11/quarantine/1700000000123/client/file.txt",
+        ] {
+            assert!(matches!(get_trash_path(_file_name), Err(GatewayError::Validation(_))), "{:?}", _file_name);
+        }
+    }
+}
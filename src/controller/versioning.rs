@@ -0,0 +1,149 @@
+use minio::s3::args::{ListObjectVersionsArgs, RemoveObjectArgs};
+use minio::s3::client::Client;
+use minio::s3::types::Item;
+use serde::Serialize;
+
+use crate::config::GatewayConfig;
+use crate::controller::metadata::get_replace_headers;
+use crate::controller::s3::{copy_object, get_object_stat, Resource};
+use crate::error::GatewayError;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ObjectVersionResponse {
+    pub file_name: String,
+    pub versions: Vec<Resource>,
+}
+
+/// S3 sends the versions and delete markers of a page newest first, but minio parses them in two separated
+/// lists, they are merged again keeping the order of each one and the latest entry first
+fn merge_delete_markers(_contents: Vec<Item>) -> Vec<Item> {
+    let (_versions, _delete_markers): (Vec<Item>, Vec<Item>) = _contents.into_iter().partition(|_content| !_content.is_delete_marker);
+    let mut _versions = _versions.into_iter().peekable();
+    let mut _delete_markers = _delete_markers.into_iter().peekable();
+    let mut _merged = Vec::new();
+    loop {
+        let _is_delete_marker_next = match (_versions.peek(), _delete_markers.peek()) {
+            (Some(_version), Some(_delete_marker)) => _delete_marker.is_latest || (!_version.is_latest && _delete_marker.last_modified > _version.last_modified),
+            (Some(_), None) => false,
+            (None, Some(_)) => true,
+            (None, None) => break,
+        };
+        let _next = match _is_delete_marker_next {
+            true => _delete_markers.next(),
+            false => _versions.next(),
+        };
+        _merged.extend(_next);
+    }
+    _merged
+}
+
+/// Versions and delete markers of an object, the newest first
+pub async fn get_list_object_versions(_client: &Client, _config: &GatewayConfig, _file_name: String) -> Result<ObjectVersionResponse, GatewayError> {
+    let mut _versions: Vec<Resource> = Vec::new();
+    let mut _key_marker: Option<String> = None;
+    let mut _version_id_marker: Option<String> = None;
+    loop {
+        let mut _args = ListObjectVersionsArgs::new(&_config.bucket_name)?;
+        //  The prefix also match other keys starting with the same name
+        _args.prefix = Some(&_file_name);
+        _args.key_marker = _key_marker.to_owned();
+        _args.version_id_marker = _version_id_marker.to_owned();
+        let _response = _client.list_object_versions(&_args).await?;
+        let _contents = _response.contents.into_iter()
+            .filter(|_content| _content.name.eq(&_file_name))
+            .collect::<Vec<Item>>();
+        _versions.extend(merge_delete_markers(_contents).iter().map(Resource::from_item));
+        if !_response.is_truncated {
+            break;
+        }
+        _key_marker = _response.next_key_marker;
+        _version_id_marker = _response.next_version_id_marker;
+    }
+    if _versions.is_empty() {
+        log::warn!("Object `{}` has no versions", _file_name);
+        return Err(GatewayError::NotFound(format!("Object `{}` does not exist", _file_name)))
+    }
+    Ok(ObjectVersionResponse {
+        file_name: _file_name,
+        versions: _versions,
+    })
+}
+
+/// Copy a previous version over the object, the restored content becomes the latest version
+pub async fn restore_object_version(_client: &Client, _config: &GatewayConfig, _file_name: String, _version_id: String) -> Result<Resource, GatewayError> {
+    let _stat = get_object_stat(_client, _config, _file_name.to_owned(), Some(_version_id.to_owned())).await?;
+    //  The metadata is replaced to allow copy an object to itself
+    let _headers = get_replace_headers(&_stat, _stat.user_metadata.to_owned());
+    copy_object(_client, _config, &_stat, &_file_name, Some(_headers)).await?;
+    let _stat = get_object_stat(_client, _config, _file_name, None).await?;
+    Ok(Resource::from_stat(&_stat))
+}
+
+/// Remove a version or a delete marker, it can not be undone
+pub async fn delete_object_version(_client: &Client, _config: &GatewayConfig, _file_name: String, _version_id: String) -> Result<(), GatewayError> {
+    let mut _args = RemoveObjectArgs::new(&_config.bucket_name, &_file_name)?;
+    _args.version_id = Some(&_version_id);
+    match _client.remove_object(&_args).await {
+        Ok(_) => Ok(()),
+        Err(error) => Err(error.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minio::s3::utils::from_iso8601utc;
+
+    fn get_item(_version_id: &str, _last_modified: &str, _is_latest: bool, _is_delete_marker: bool) -> Item {
+        Item {
+            name: "11/client/attachment/c_order/1/file.txt".to_string(),
+            last_modified: Some(from_iso8601utc(_last_modified).unwrap()),
+            etag: None,
+            owner_id: None,
+            owner_name: None,
+            size: None,
+            storage_class: None,
+            is_latest: _is_latest,
+            version_id: Some(_version_id.to_string()),
+            user_metadata: None,
+            is_prefix: false,
+            is_delete_marker: _is_delete_marker,
+            encoding_type: None,
+        }
+    }
+
+    fn get_version_ids(_contents: Vec<Item>) -> Vec<String> {
+        merge_delete_markers(_contents).into_iter()
+            .filter_map(|_content| _content.version_id)
+            .collect()
+    }
+
+    #[test]
+    fn delete_markers_are_merged_by_date() {
+        //  Order parsed by minio, versions first
+        let _contents = vec![
+            get_item("v3", "2024-01-01T10:00:03.000Z", false, false),
+            get_item("v1", "2024-01-01T10:00:01.000Z", false, false),
+            get_item("m4", "2024-01-01T10:00:04.000Z", true, true),
+            get_item("m2", "2024-01-01T10:00:02.000Z", false, true),
+        ];
+        assert_eq!(get_version_ids(_contents), vec!["m4", "v3", "m2", "v1"]);
+    }
+
+    #[test]
+    fn latest_entry_is_first_in_the_same_second() {
+        //  Without milliseconds only the S3 order and `is_latest` are reliable
+        let _contents = vec![
+            get_item("v2", "2024-01-01T10:00:00Z", true, false),
+            get_item("v1", "2024-01-01T10:00:00Z", false, false),
+            get_item("m1", "2024-01-01T10:00:00Z", false, true),
+        ];
+        assert_eq!(get_version_ids(_contents), vec!["v2", "v1", "m1"]);
+        let _contents = vec![
+            get_item("v2", "2024-01-01T10:00:00Z", false, false),
+            get_item("v1", "2024-01-01T10:00:00Z", false, false),
+            get_item("m1", "2024-01-01T10:00:00Z", true, true),
+        ];
+        assert_eq!(get_version_ids(_contents), vec!["m1", "v2", "v1"]);
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt;
+
+use salvo::http::StatusCode;
+use salvo::prelude::Json;
+use salvo::{Response, Scribe};
+use serde::Serialize;
+
+/// Errors surfaced by the gateway, each one maps to a HTTP status and a stable code
+#[derive(Debug, Clone)]
+pub enum GatewayError {
+    /// Wrong or missing parameters sent by the caller
+    Validation(String),
+    /// The requested object or bucket does not exist
+    NotFound(String),
+    /// The caller did not send valid credentials
+    Unauthorized(String),
+    /// The caller is not allowed to access the resource
+    Forbidden(String),
+    /// The object already exists and the upload must not replace it
+    Conflict(String),
+    /// The upload exceeds the size allowed by the upload policy
+    PayloadTooLarge(String),
+    /// The content type of the upload is not allowed by the upload policy
+    UnsupportedMediaType(String),
+    /// The antivirus found a threat in the upload
+    Infected(String),
+    /// The requested range can not be served for the object
+    InvalidRange(String),
+    /// A conditional header (`If-Match`, `If-Unmodified-Since`) does not match the object
+    PreconditionFailed(String),
+    /// The S3 service failed or is not reachable
+    Upstream(String),
+    /// The gateway is not configured properly
+    Config(String),
+}
+
+impl GatewayError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            GatewayError::Validation(_) => StatusCode::BAD_REQUEST,
+            GatewayError::NotFound(_) => StatusCode::NOT_FOUND,
+            GatewayError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            GatewayError::Forbidden(_) => StatusCode::FORBIDDEN,
+            GatewayError::Conflict(_) => StatusCode::CONFLICT,
+            GatewayError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            GatewayError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            GatewayError::Infected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            GatewayError::InvalidRange(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            GatewayError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            GatewayError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            GatewayError::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Machine-readable code sent to clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            GatewayError::Validation(_) => "VALIDATION_ERROR",
+            GatewayError::NotFound(_) => "NOT_FOUND",
+            GatewayError::Unauthorized(_) => "UNAUTHORIZED",
+            GatewayError::Forbidden(_) => "FORBIDDEN",
+            GatewayError::Conflict(_) => "CONFLICT",
+            GatewayError::PayloadTooLarge(_) => "PAYLOAD_TOO_LARGE",
+            GatewayError::UnsupportedMediaType(_) => "UNSUPPORTED_MEDIA_TYPE",
+            GatewayError::Infected(_) => "INFECTED",
+            GatewayError::InvalidRange(_) => "INVALID_RANGE",
+            GatewayError::PreconditionFailed(_) => "PRECONDITION_FAILED",
+            GatewayError::Upstream(_) => "UPSTREAM_ERROR",
+            GatewayError::Config(_) => "CONFIG_ERROR",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            GatewayError::Validation(message)
+            | GatewayError::NotFound(message)
+            | GatewayError::Unauthorized(message)
+            | GatewayError::Forbidden(message)
+            | GatewayError::Conflict(message)
+            | GatewayError::PayloadTooLarge(message)
+            | GatewayError::UnsupportedMediaType(message)
+            | GatewayError::Infected(message)
+            | GatewayError::InvalidRange(message)
+            | GatewayError::PreconditionFailed(message)
+            | GatewayError::Upstream(message)
+            | GatewayError::Config(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for GatewayError {}
+
+impl From<minio::s3::error::Error> for GatewayError {
+    fn from(error: minio::s3::error::Error) -> Self {
+        match error {
+            minio::s3::error::Error::S3Error(ref response) => match response.code.as_str() {
+                "NoSuchKey" | "NoSuchBucket" | "NoSuchUpload" | "NoSuchVersion" | "ResourceNotFound" => GatewayError::NotFound(response.message.to_owned()),
+                "AccessDenied" => GatewayError::Forbidden(response.message.to_owned()),
+                "InvalidRange" => GatewayError::InvalidRange(response.message.to_owned()),
+                "PreconditionFailed" => GatewayError::PreconditionFailed(response.message.to_owned()),
+                _ => GatewayError::Upstream(error.to_string()),
+            },
+            minio::s3::error::Error::InvalidBucketName(message) => GatewayError::Config(message),
+            minio::s3::error::Error::InvalidObjectName(message) => GatewayError::Validation(message),
+            _ => GatewayError::Upstream(error.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ErrorResponse {
+    pub status: u16,
+    pub code: String,
+    pub message: String
+}
+
+impl From<&GatewayError> for ErrorResponse {
+    fn from(error: &GatewayError) -> Self {
+        ErrorResponse {
+            status: error.status_code().into(),
+            code: error.code().to_owned(),
+            message: error.message().to_owned(),
+        }
+    }
+}
+
+impl Scribe for GatewayError {
+    fn render(self, _res: &mut Response) {
+        _res.status_code(self.status_code());
+        _res.render(Json(ErrorResponse::from(&self)));
+    }
+}
@@ -1,4 +1,7 @@
+pub mod auth;
+pub mod config;
 pub mod controller;
+pub mod error;
 // #[cfg(test)]
 // mod tests {
 //     use crate::{models::menu::Menu, controller::opensearch::{create_index_definition, IndexDocument, create, delete_index_definition, delete, find}};